@@ -0,0 +1,22 @@
+BEGIN;
+  CREATE TYPE subscription_status AS ENUM (
+    'PENDING_CONFIRMATION',
+    'CONFIRMED',
+    'UNSUBSCRIBED'
+  );
+
+  ALTER TABLE subscriptions
+    ALTER COLUMN status TYPE subscription_status
+    USING status::subscription_status;
+
+  CREATE TABLE subscription_status_history(
+    id uuid PRIMARY KEY,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions(id) ON DELETE CASCADE,
+    from_status subscription_status NULL,
+    to_status subscription_status NOT NULL,
+    changed_at timestamptz NOT NULL
+  );
+
+  CREATE INDEX subscription_status_history_subscriber_id_idx
+    ON subscription_status_history (subscriber_id, changed_at);
+COMMIT;
@@ -0,0 +1,37 @@
+-- Keep the address as typed by the subscriber for display purposes and
+-- store a normalised one (lowercase domain) in `email`.
+BEGIN;
+  ALTER TABLE subscriptions ADD COLUMN display_email TEXT NULL;
+
+  UPDATE subscriptions SET display_email = email;
+
+  ALTER TABLE subscriptions ALTER COLUMN display_email SET NOT NULL;
+
+  -- Lowercasing domains can make two addresses equal, so the exact-match
+  -- constraint has to go first
+  ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+
+  UPDATE subscriptions
+    SET email = substring(email FROM '^(.*)@') || '@' || lower(substring(email FROM '@([^@]*)$'))
+    WHERE email LIKE '%@%';
+
+  -- Addresses differing only by case are the same subscriber, keep one of
+  -- them: an unsubscribed one so an opt-out is never lost, else a confirmed
+  -- one, else the oldest
+  CREATE TEMPORARY TABLE duplicate_subscriptions ON COMMIT DROP AS
+    SELECT id FROM (
+      SELECT id, row_number() OVER (
+        PARTITION BY lower(email)
+        ORDER BY status = 'UNSUBSCRIBED' DESC, status = 'CONFIRMED' DESC, subscribed_at, id
+      ) AS rank
+      FROM subscriptions
+    ) ranked
+    WHERE rank > 1;
+
+  DELETE FROM subscription_tokens
+    WHERE subscriber_id IN (SELECT id FROM duplicate_subscriptions);
+  DELETE FROM subscriptions
+    WHERE id IN (SELECT id FROM duplicate_subscriptions);
+
+  CREATE UNIQUE INDEX subscriptions_email_lower_key ON subscriptions (LOWER(email));
+COMMIT;
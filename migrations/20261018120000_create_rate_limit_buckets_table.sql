@@ -0,0 +1,6 @@
+-- Token buckets shared by every instance of the application
+CREATE TABLE rate_limit_buckets(
+  key TEXT NOT NULL PRIMARY KEY,
+  tokens DOUBLE PRECISION NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Failed credential checks per username and per client IP
+CREATE TABLE login_failures(
+  key TEXT NOT NULL PRIMARY KEY,
+  failures INTEGER NOT NULL,
+  last_failure_at TIMESTAMPTZ NOT NULL,
+  locked_until TIMESTAMPTZ NULL
+);
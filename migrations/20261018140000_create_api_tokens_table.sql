@@ -0,0 +1,13 @@
+-- Personal API tokens, only the SHA-256 hash of the token is stored
+CREATE TABLE api_tokens(
+  id uuid NOT NULL PRIMARY KEY,
+  user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  scopes TEXT[] NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL,
+  expires_at TIMESTAMPTZ NULL,
+  last_used_at TIMESTAMPTZ NULL
+);
+
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
@@ -0,0 +1,7 @@
+-- Existing users could do everything, they become admins
+BEGIN;
+  CREATE TYPE user_role AS ENUM ('ADMIN', 'EDITOR', 'VIEWER');
+
+  ALTER TABLE users ADD COLUMN role user_role NOT NULL DEFAULT 'ADMIN';
+  ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
+COMMIT;
@@ -0,0 +1,5 @@
+-- Disabled users keep their data but can no longer authenticate
+BEGIN;
+  ALTER TABLE users ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+  ALTER TABLE users ADD COLUMN disabled_at TIMESTAMPTZ NULL;
+COMMIT;
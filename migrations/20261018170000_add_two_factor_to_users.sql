@@ -0,0 +1,14 @@
+-- Optional TOTP second factor. The secret is set when enrolment starts and
+-- only enforced once totp_enabled_at is set by confirming a first code.
+ALTER TABLE users
+  ADD COLUMN totp_secret TEXT NULL,
+  ADD COLUMN totp_enabled_at TIMESTAMPTZ NULL,
+  ADD COLUMN totp_last_step BIGINT NULL;
+
+-- Single use recovery codes, only their SHA-256 hash is stored
+CREATE TABLE recovery_codes(
+  user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+  code_hash TEXT NOT NULL,
+  used_at TIMESTAMPTZ NULL,
+  PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,14 @@
+-- Where password reset links are sent, optional for existing users
+ALTER TABLE users ADD COLUMN email TEXT NULL;
+CREATE UNIQUE INDEX users_email_key ON users (lower(email));
+
+-- Single use password reset tokens, only their SHA-256 hash is stored
+CREATE TABLE password_reset_tokens(
+  token_hash TEXT NOT NULL PRIMARY KEY,
+  user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+  created_at TIMESTAMPTZ NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at TIMESTAMPTZ NULL
+);
+
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id);
@@ -0,0 +1,30 @@
+-- Who did what and when. No foreign keys, entries must outlive the users
+-- and subscribers they mention.
+CREATE TABLE audit_log(
+  id BIGSERIAL PRIMARY KEY,
+  occurred_at TIMESTAMPTZ NOT NULL,
+  action TEXT NOT NULL,
+  actor_user_id uuid NULL,
+  target_id TEXT NULL,
+  request_id TEXT NULL,
+  details JSONB NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX audit_log_action_idx ON audit_log (action, id);
+CREATE INDEX audit_log_actor_user_id_idx ON audit_log (actor_user_id, id);
+CREATE INDEX audit_log_occurred_at_idx ON audit_log (occurred_at);
+
+-- Append-only, even for the application's own database user
+CREATE FUNCTION reject_audit_log_change() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+  BEFORE UPDATE OR DELETE ON audit_log
+  FOR EACH ROW EXECUTE FUNCTION reject_audit_log_change();
+
+CREATE TRIGGER audit_log_no_truncate
+  BEFORE TRUNCATE ON audit_log
+  FOR EACH STATEMENT EXECUTE FUNCTION reject_audit_log_change();
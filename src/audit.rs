@@ -0,0 +1,148 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::request_id::current_request_id;
+
+/// An action recorded in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    LoginSucceeded,
+    LoginFailed,
+    LoginLockedOut,
+    NewsletterPublished,
+    UserCreated,
+    UserDisabled,
+    UserDeleted,
+    UserEmailChanged,
+    UserTwoFactorReset,
+    AdminBootstrapped,
+    SubscriberStatusChanged,
+    SubscriberDeleted,
+    SubscribersImported,
+    ApiTokenCreated,
+    ApiTokenRevoked,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    RecoveryCodesRegenerated,
+    PasswordReset,
+    MigrationsApplied,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::LoginSucceeded => "login_succeeded",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::LoginLockedOut => "login_locked_out",
+            AuditAction::NewsletterPublished => "newsletter_published",
+            AuditAction::UserCreated => "user_created",
+            AuditAction::UserDisabled => "user_disabled",
+            AuditAction::UserDeleted => "user_deleted",
+            AuditAction::UserEmailChanged => "user_email_changed",
+            AuditAction::UserTwoFactorReset => "user_two_factor_reset",
+            AuditAction::AdminBootstrapped => "admin_bootstrapped",
+            AuditAction::SubscriberStatusChanged => "subscriber_status_changed",
+            AuditAction::SubscriberDeleted => "subscriber_deleted",
+            AuditAction::SubscribersImported => "subscribers_imported",
+            AuditAction::ApiTokenCreated => "api_token_created",
+            AuditAction::ApiTokenRevoked => "api_token_revoked",
+            AuditAction::TwoFactorEnabled => "two_factor_enabled",
+            AuditAction::TwoFactorDisabled => "two_factor_disabled",
+            AuditAction::RecoveryCodesRegenerated => "recovery_codes_regenerated",
+            AuditAction::PasswordReset => "password_reset",
+            AuditAction::MigrationsApplied => "migrations_applied",
+        }
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// An audit log entry about to be recorded.
+#[derive(Debug)]
+pub struct AuditEvent {
+    action: AuditAction,
+    actor_user_id: Option<Uuid>,
+    target_id: Option<String>,
+    details: serde_json::Value,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            actor_user_id: None,
+            target_id: None,
+            details: serde_json::json!({}),
+        }
+    }
+
+    /// The user performing the action.
+    pub fn by(mut self, user_id: Uuid) -> Self {
+        self.actor_user_id = Some(user_id);
+        self
+    }
+
+    /// The user, subscriber or token the action is about.
+    pub fn on(mut self, target_id: impl ToString) -> Self {
+        self.target_id = Some(target_id.to_string());
+        self
+    }
+
+    /// Context specific to the action. Never put secrets in here.
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+
+    /// Appends the event to the audit log, tagged with the current request
+    /// id. Pass the transaction of the action when there is one, so that
+    /// the entry is only kept if the action is.
+    #[tracing::instrument(name = "Record audit event", skip(self, executor), fields(action = %self.action))]
+    pub async fn record<'c>(self, executor: impl PgExecutor<'c>) -> Result<(), anyhow::Error> {
+        let request_id = current_request_id();
+
+        sqlx::query!(
+            r#"INSERT INTO audit_log (occurred_at, action, actor_user_id, target_id, request_id, details)
+            VALUES ($1, $2, $3, $4, $5, $6)"#,
+            Utc::now(),
+            self.action.as_str(),
+            self.actor_user_id,
+            self.target_id,
+            request_id.as_ref().map(|id| id.as_ref()),
+            self.details
+        )
+        .execute(executor)
+        .await
+        .context("Failed to record audit event")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditAction;
+
+    #[test]
+    fn action_names_match_their_serialized_form() {
+        for action in [
+            AuditAction::LoginSucceeded,
+            AuditAction::NewsletterPublished,
+            AuditAction::UserTwoFactorReset,
+            AuditAction::SubscriberStatusChanged,
+            AuditAction::RecoveryCodesRegenerated,
+        ] {
+            assert_eq!(
+                serde_json::to_value(action).unwrap(),
+                serde_json::Value::String(action.as_str().into())
+            );
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::AuthError;
+
+/// Prefix making leaked tokens easy to recognise, e.g. by secret scanners.
+const API_TOKEN_PREFIX: &str = "z2p_";
+const API_TOKEN_LENGTH: usize = 40;
+
+/// What an API token is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ApiTokenScope {
+    #[serde(rename = "newsletter:publish")]
+    PublishNewsletter,
+    #[serde(rename = "subscribers:manage")]
+    ManageSubscribers,
+}
+
+impl ApiTokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiTokenScope::PublishNewsletter => "newsletter:publish",
+            ApiTokenScope::ManageSubscribers => "subscribers:manage",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<ApiTokenScope, String> {
+        match s {
+            "newsletter:publish" => Ok(ApiTokenScope::PublishNewsletter),
+            "subscribers:manage" => Ok(ApiTokenScope::ManageSubscribers),
+            other => Err(format!("{} is not a valid API token scope", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiTokenScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Generates a new token, returning it along with the hash to store.
+pub fn generate_api_token() -> (Secret<String>, String) {
+    let random: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(API_TOKEN_LENGTH)
+        .collect();
+    let token = format!("{}{}", API_TOKEN_PREFIX, random);
+    let hash = hash_api_token(&token);
+
+    (Secret::new(token), hash)
+}
+
+/// Tokens are long random strings, a plain SHA-256 is enough to protect
+/// them at rest and lets us look them up by hash.
+pub fn hash_api_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Extracts the token of a `Bearer` Authorization header, `None` if the
+/// request uses another scheme.
+pub fn bearer_token(headers: &HeaderMap) -> Result<Option<Secret<String>>, anyhow::Error> {
+    let Some(header_value) = headers.get("Authorization") else {
+        return Ok(None);
+    };
+
+    let header_value = header_value
+        .to_str()
+        .context("'Authorization' header was not a valid utf-8 string")?;
+
+    Ok(header_value
+        .strip_prefix("Bearer ")
+        .map(|token| Secret::new(token.trim().to_string())))
+}
+
+/// Checks that `token` is a live API token holding `scope` and returns
+/// the id of the user it belongs to.
+#[tracing::instrument(
+    name = "Validate API token",
+    skip(token, db_pool),
+    fields(api_token_id = tracing::field::Empty)
+)]
+pub async fn validate_api_token(
+    token: Secret<String>,
+    scope: ApiTokenScope,
+    db_pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let row = sqlx::query!(
+        r#"SELECT t.id, t.user_id, t.scopes, t.expires_at
+        FROM api_tokens t JOIN users u ON u.user_id = t.user_id
+        WHERE t.token_hash = $1 AND u.disabled_at IS NULL"#,
+        hash_api_token(token.expose_secret())
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to fetch API token")?
+    .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown API token")))?;
+
+    let now = Utc::now();
+    if row.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "Expired API token"
+        )));
+    }
+
+    if !row.scopes.iter().any(|s| s == scope.as_str()) {
+        return Err(AuthError::MissingScope(scope));
+    }
+
+    sqlx::query!(
+        r#"UPDATE api_tokens SET last_used_at = $2 WHERE id = $1"#,
+        row.id,
+        now
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to update API token last use")?;
+
+    tracing::Span::current().record("api_token_id", tracing::field::display(&row.id));
+
+    Ok(row.user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+    use claims::{assert_none, assert_ok};
+    use secrecy::ExposeSecret;
+
+    use super::{bearer_token, generate_api_token, hash_api_token, ApiTokenScope};
+
+    #[test]
+    fn generated_token_matches_its_hash() {
+        let (token, hash) = generate_api_token();
+
+        assert!(token.expose_secret().starts_with("z2p_"));
+        assert_eq!(hash_api_token(token.expose_secret()), hash);
+        assert_ne!(token.expose_secret(), &hash);
+    }
+
+    #[test]
+    fn bearer_token_is_extracted() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer z2p_abc"));
+
+        let token = bearer_token(&headers).unwrap().unwrap();
+
+        assert_eq!(token.expose_secret(), "z2p_abc");
+    }
+
+    #[test]
+    fn other_schemes_are_not_bearer_tokens() {
+        let mut headers = HeaderMap::new();
+        assert_none!(bearer_token(&headers).unwrap());
+
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_static("Basic Zm9vOmJhcg=="),
+        );
+        assert_none!(bearer_token(&headers).unwrap());
+    }
+
+    #[test]
+    fn scopes_round_trip() {
+        for scope in [
+            ApiTokenScope::PublishNewsletter,
+            ApiTokenScope::ManageSubscribers,
+        ] {
+            assert_eq!(assert_ok!(ApiTokenScope::parse(scope.as_str())), scope);
+        }
+    }
+}
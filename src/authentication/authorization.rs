@@ -0,0 +1,166 @@
+use std::{future::Future, marker::PhantomData, pin::Pin};
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{authenticate, authenticate_with_password, ApiTokenScope};
+use crate::{domain::UserRole, error::AppError};
+
+/// An action a user needs a role for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ViewStats,
+    PublishNewsletter,
+    ManageSubscribers,
+    ManageUsers,
+    ViewAuditLog,
+}
+
+impl Permission {
+    /// The lowest role allowed to perform this action.
+    pub fn required_role(&self) -> UserRole {
+        match self {
+            Permission::ViewStats => UserRole::Viewer,
+            Permission::PublishNewsletter => UserRole::Editor,
+            Permission::ManageSubscribers | Permission::ManageUsers | Permission::ViewAuditLog => {
+                UserRole::Admin
+            }
+        }
+    }
+
+    /// The scope an API token needs for this action, `None` if it requires
+    /// a password.
+    fn api_token_scope(&self) -> Option<ApiTokenScope> {
+        match self {
+            Permission::PublishNewsletter => Some(ApiTokenScope::PublishNewsletter),
+            Permission::ManageSubscribers => Some(ApiTokenScope::ManageSubscribers),
+            Permission::ViewStats | Permission::ManageUsers | Permission::ViewAuditLog => None,
+        }
+    }
+
+    fn realm(&self) -> &'static str {
+        match self {
+            Permission::PublishNewsletter => "publish",
+            _ => "admin",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Permission::ViewStats => "view stats",
+            Permission::PublishNewsletter => "publish newsletters",
+            Permission::ManageSubscribers => "manage subscribers",
+            Permission::ManageUsers => "manage users",
+            Permission::ViewAuditLog => "view the audit log",
+        }
+    }
+}
+
+/// Type level `Permission`, to be used with `Authorized`.
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+pub struct ViewStats;
+pub struct PublishNewsletter;
+pub struct ManageSubscribers;
+pub struct ManageUsers;
+pub struct ViewAuditLog;
+
+impl RequiredPermission for ViewStats {
+    const PERMISSION: Permission = Permission::ViewStats;
+}
+
+impl RequiredPermission for PublishNewsletter {
+    const PERMISSION: Permission = Permission::PublishNewsletter;
+}
+
+impl RequiredPermission for ManageSubscribers {
+    const PERMISSION: Permission = Permission::ManageSubscribers;
+}
+
+impl RequiredPermission for ManageUsers {
+    const PERMISSION: Permission = Permission::ManageUsers;
+}
+
+impl RequiredPermission for ViewAuditLog {
+    const PERMISSION: Permission = Permission::ViewAuditLog;
+}
+
+/// Extractor authenticating the caller and checking that their role grants
+/// the permission `P`. Requests are rejected with a 401 when the caller
+/// cannot be authenticated and a 403 when their role is not enough.
+pub struct Authorized<P> {
+    pub user_id: Uuid,
+    pub role: UserRole,
+    permission: PhantomData<P>,
+}
+
+impl<P: RequiredPermission + 'static> FromRequest for Authorized<P> {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let (user_id, role) = authorize(&req, P::PERMISSION).await?;
+
+            Ok(Self {
+                user_id,
+                role,
+                permission: PhantomData,
+            })
+        })
+    }
+}
+
+#[tracing::instrument(
+    name = "Authorize request",
+    skip(request),
+    fields(user_id=tracing::field::Empty, role=tracing::field::Empty)
+)]
+async fn authorize(
+    request: &HttpRequest,
+    permission: Permission,
+) -> Result<(Uuid, UserRole), AppError> {
+    let db_pool = request
+        .app_data::<web::Data<PgPool>>()
+        .context("Database pool is not configured")?;
+
+    let user_id = match permission.api_token_scope() {
+        Some(scope) => authenticate(request, db_pool, scope).await,
+        None => authenticate_with_password(request, db_pool).await,
+    }
+    .map_err(|e| AppError::from_auth_error(permission.realm(), e))?;
+
+    let role = get_user_role(user_id, db_pool)
+        .await
+        .context("Failed to fetch user role")?;
+
+    tracing::Span::current()
+        .record("user_id", tracing::field::display(&user_id))
+        .record("role", tracing::field::display(&role));
+
+    if !role.includes(permission.required_role()) {
+        return Err(AppError::Forbidden(format!(
+            "The {} role is not allowed to {}",
+            role,
+            permission.description()
+        )));
+    }
+
+    Ok((user_id, role))
+}
+
+#[tracing::instrument(name = "Get user role", skip(db_pool))]
+async fn get_user_role(user_id: Uuid, db_pool: &PgPool) -> Result<UserRole, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT role AS "role: UserRole" FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(db_pool)
+    .await
+}
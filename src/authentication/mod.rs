@@ -0,0 +1,357 @@
+mod api_token;
+mod authorization;
+mod password;
+mod throttle;
+mod two_factor;
+
+use std::time::Duration;
+
+use actix_web::{http::header::HeaderMap, web, HttpRequest};
+use anyhow::Context;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::Engine;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    client_ip::ClientIp,
+    telemetry::spawn_blocking_with_tracing,
+};
+
+pub use api_token::*;
+pub use authorization::*;
+pub use password::*;
+pub use throttle::*;
+pub use two_factor::*;
+
+/// Successful credential checks of a user from the same client IP within
+/// this interval are audited as a single login.
+pub const LOGIN_AUDIT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+    /// TOTP or recovery code, required for users with two-factor enabled.
+    pub totp_code: Option<Secret<String>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials")]
+    InvalidCredentials(#[source] anyhow::Error),
+
+    #[error("A two-factor code is required in the {} header", TOTP_CODE_HEADER)]
+    SecondFactorRequired,
+
+    #[error("Too many failed login attempts")]
+    LockedOut { retry_after: Duration },
+
+    #[error("The API token is missing the {0} scope")]
+    MissingScope(ApiTokenScope),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Authenticates a request carrying either an API token with `scope` or
+/// Basic credentials, and returns the id of the authenticated user.
+pub async fn authenticate(
+    request: &HttpRequest,
+    db_pool: &PgPool,
+    scope: ApiTokenScope,
+) -> Result<uuid::Uuid, AuthError> {
+    match bearer_token(request.headers()).map_err(AuthError::InvalidCredentials)? {
+        Some(token) => validate_api_token(token, scope, db_pool).await,
+        None => authenticate_with_password(request, db_pool).await,
+    }
+}
+
+/// Authenticates a request with Basic credentials only, for actions an
+/// API token must not be able to perform.
+#[tracing::instrument(
+    name = "Authenticate with password",
+    skip(request, db_pool),
+    fields(username=tracing::field::Empty)
+)]
+pub async fn authenticate_with_password(
+    request: &HttpRequest,
+    db_pool: &PgPool,
+) -> Result<uuid::Uuid, AuthError> {
+    let credentials =
+        basic_authentication(request.headers()).map_err(AuthError::InvalidCredentials)?;
+
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    let login_throttle = request
+        .app_data::<web::Data<LoginThrottle>>()
+        .context("Login throttle is not configured")?;
+    let password_hashing = request
+        .app_data::<web::Data<PasswordHashing>>()
+        .context("Password hashing is not configured")?;
+    let ClientIp(client_ip) = ClientIp::of(request);
+
+    validate_credentials(
+        credentials,
+        client_ip.as_deref(),
+        db_pool,
+        login_throttle,
+        password_hashing,
+    )
+    .await
+}
+
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("'Authorization' header missing")?
+        .to_str()
+        .context("'Authorization' header was not a valid utf-8 string")?;
+
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The Authorization scheme was not Basic")?;
+
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to decode base64 credentials")?;
+
+    let decoded_credentials =
+        String::from_utf8(decoded_bytes).context("The decoded credentials is not valid utf-8")?;
+
+    let mut credentials = decoded_credentials.splitn(2, ':');
+
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Username must be provided"))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Password must be provided"))?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: Secret::new(password),
+        totp_code: totp_code(headers)?,
+    })
+}
+
+/// Checks `credentials` against the stored ones, throttling repeated
+/// failures from the same username or client IP. The stored hash is
+/// upgraded when it is weaker than the `password_hashing` target.
+///
+/// Every failed check is audited, their number being bounded by the
+/// throttle. Credentials are sent with every request, so a successful check
+/// is only audited as a login when the user did not log in from the same
+/// client IP within `LOGIN_AUDIT_INTERVAL`.
+#[tracing::instrument(
+    name = "Validate credentials",
+    skip(credentials, db_pool, login_throttle, password_hashing)
+)]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    client_ip: Option<&str>,
+    db_pool: &PgPool,
+    login_throttle: &LoginThrottle,
+    password_hashing: &PasswordHashing,
+) -> Result<uuid::Uuid, AuthError> {
+    let username = credentials.username.clone();
+
+    login_throttle
+        .before_attempt(&username, client_ip, db_pool)
+        .await?;
+
+    match verify_credentials(credentials, db_pool, password_hashing).await {
+        Ok(user_id) => {
+            login_throttle.record_success(&username, db_pool).await?;
+            if !logged_in_recently(user_id, client_ip, db_pool).await? {
+                AuditEvent::new(AuditAction::LoginSucceeded)
+                    .by(user_id)
+                    .with_details(serde_json::json!({
+                        "username": username,
+                        "client_ip": client_ip,
+                    }))
+                    .record(db_pool)
+                    .await?;
+            }
+            Ok(user_id)
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            let lockouts = login_throttle
+                .record_failure(&username, client_ip, db_pool)
+                .await?;
+            AuditEvent::new(AuditAction::LoginFailed)
+                .with_details(serde_json::json!({
+                    "username": username,
+                    "client_ip": client_ip,
+                }))
+                .record(db_pool)
+                .await?;
+            if !lockouts.is_empty() {
+                AuditEvent::new(AuditAction::LoginLockedOut)
+                    .with_details(serde_json::json!({
+                        "username": username,
+                        "client_ip": client_ip,
+                        "lockouts": lockouts,
+                    }))
+                    .record(db_pool)
+                    .await?;
+            }
+            Err(AuthError::InvalidCredentials(e))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether a login of `user_id` from `client_ip` was audited within
+/// `LOGIN_AUDIT_INTERVAL`.
+async fn logged_in_recently(
+    user_id: uuid::Uuid,
+    client_ip: Option<&str>,
+    db_pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let since = chrono::Utc::now() - LOGIN_AUDIT_INTERVAL;
+
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+            SELECT 1 FROM audit_log
+            WHERE actor_user_id = $1
+              AND action = $2
+              AND details->>'client_ip' IS NOT DISTINCT FROM $3
+              AND occurred_at > $4
+        ) AS "exists!""#,
+        user_id,
+        AuditAction::LoginSucceeded.as_str(),
+        client_ip,
+        since
+    )
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to look for a recent login")
+}
+
+async fn verify_credentials(
+    credentials: Credentials,
+    db_pool: &PgPool,
+    password_hashing: &PasswordHashing,
+) -> Result<uuid::Uuid, AuthError> {
+    let mut user_id = None;
+    let mut expected_password_hash = password_hashing.dummy_hash();
+
+    if let Some((stored_user_id, stored_password)) =
+        get_stored_credentials(&credentials.username, db_pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password;
+    }
+
+    let Credentials {
+        username,
+        password,
+        totp_code,
+    } = credentials;
+    let stored_password_hash = expected_password_hash.clone();
+    let verified_password = password.clone();
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, verified_password)
+    })
+    .await
+    // spawn_blocking is fallible - we have a nested Result here!
+    .context("Failed to spawn blocking task.")??;
+
+    let user_id = user_id
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username")))?;
+
+    verify_second_factor(user_id, &username, totp_code, db_pool).await?;
+
+    // The login succeeded already, a failed upgrade is retried next time
+    if let Err(e) = upgrade_password_hash(
+        user_id,
+        stored_password_hash,
+        password,
+        password_hashing,
+        db_pool,
+    )
+    .await
+    {
+        tracing::warn!(error.cause_chain = ?e, "Failed to upgrade password hash");
+    }
+
+    Ok(user_id)
+}
+
+#[tracing::instrument(
+    name = "Upgrade password hash",
+    skip(stored_password_hash, password, password_hashing, db_pool)
+)]
+async fn upgrade_password_hash(
+    user_id: uuid::Uuid,
+    stored_password_hash: Secret<String>,
+    password: Secret<String>,
+    password_hashing: &PasswordHashing,
+    db_pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    if !password_hashing.needs_rehash(stored_password_hash.expose_secret())? {
+        return Ok(());
+    }
+
+    let password_hash = password_hashing.hash(password).await?;
+
+    // Only replace the hash that was verified, not a concurrently changed one
+    sqlx::query!(
+        r#"UPDATE users SET password_hash = $2 WHERE user_id = $1 AND password_hash = $3"#,
+        user_id,
+        password_hash.expose_secret(),
+        stored_password_hash.expose_secret()
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to store upgraded password hash")?;
+
+    tracing::info!("Upgraded password hash to the current parameters");
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format.")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid Password.")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, db_pool))]
+async fn get_stored_credentials(
+    username: &str,
+    db_pool: &PgPool,
+) -> Result<Option<(uuid::Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+    SELECT user_id, password_hash
+    FROM users
+    WHERE username = $1 AND disabled_at IS NULL
+    "#,
+        username,
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to fetch credentials from database")?
+    .map(|r| (r.user_id, Secret::new(r.password_hash)));
+
+    Ok(row)
+}
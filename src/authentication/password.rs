@@ -0,0 +1,139 @@
+use anyhow::Context;
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher, Version,
+};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{configuration::PasswordHashingSettings, telemetry::spawn_blocking_with_tracing};
+
+/// Argon2id parameters new password hashes are computed with. Stored hashes
+/// computed with weaker parameters are upgraded on the next login.
+#[derive(Clone)]
+pub struct PasswordHashing {
+    params: Params,
+    /// Verified against when the username is unknown, so that the response
+    /// time does not tell whether a user exists.
+    dummy_hash: Secret<String>,
+}
+
+impl PasswordHashing {
+    pub fn new(settings: &PasswordHashingSettings) -> Result<Self, anyhow::Error> {
+        let params = Params::new(
+            settings.memory_kib,
+            settings.iterations,
+            settings.parallelism,
+            None,
+        )
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("Invalid Argon2 parameters")?;
+
+        let dummy_hash = hash_with(&params, &Secret::new("dummy password".to_string()))?;
+
+        Ok(Self { params, dummy_hash })
+    }
+
+    pub fn dummy_hash(&self) -> Secret<String> {
+        self.dummy_hash.clone()
+    }
+
+    /// Hashes `password` with Argon2id, to be stored as a PHC string.
+    pub async fn hash(&self, password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+        let params = self.params.clone();
+
+        spawn_blocking_with_tracing(move || hash_with(&params, &password))
+            .await
+            .context("Failed to spawn blocking task.")?
+    }
+
+    /// Whether a stored hash uses another algorithm, an older version or
+    /// weaker parameters than the current target.
+    pub fn needs_rehash(&self, stored_hash: &str) -> Result<bool, anyhow::Error> {
+        let stored_hash =
+            PasswordHash::new(stored_hash).context("Failed to parse hash in PHC string format.")?;
+
+        if stored_hash.algorithm != Algorithm::Argon2id.ident()
+            || stored_hash.version != Some(Version::V0x13.into())
+        {
+            return Ok(true);
+        }
+
+        let stored_params = Params::try_from(&stored_hash)
+            .map_err(|e| anyhow::anyhow!(e))
+            .context("Failed to read Argon2 parameters")?;
+
+        Ok(stored_params.m_cost() < self.params.m_cost()
+            || stored_params.t_cost() < self.params.t_cost()
+            || stored_params.p_cost() < self.params.p_cost())
+    }
+}
+
+fn hash_with(params: &Params, password: &Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("Failed to hash password")?
+        .to_string();
+
+    Ok(Secret::new(password_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_ok_eq;
+    use secrecy::ExposeSecret;
+
+    use super::PasswordHashing;
+    use crate::configuration::PasswordHashingSettings;
+
+    fn hashing() -> PasswordHashing {
+        PasswordHashing::new(&PasswordHashingSettings {
+            memory_kib: 19456,
+            iterations: 2,
+            parallelism: 1,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn hashes_use_the_target_parameters() {
+        let hashing = hashing();
+
+        let hash = hashing
+            .hash(secrecy::Secret::new("password".into()))
+            .await
+            .unwrap();
+
+        assert!(hash
+            .expose_secret()
+            .starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+        assert_ok_eq!(hashing.needs_rehash(hash.expose_secret()), false);
+    }
+
+    #[test]
+    fn weaker_or_older_hashes_need_rehash() {
+        let hashing = hashing();
+
+        for stored_hash in [
+            "$argon2id$v=19$m=15000,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2id$v=19$m=19456,t=1,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2i$v=19$m=19456,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+            "$argon2id$v=16$m=19456,t=2,p=1$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+        ] {
+            assert_ok_eq!(hashing.needs_rehash(stored_hash), true);
+        }
+    }
+
+    #[test]
+    fn stronger_hashes_are_kept() {
+        let hashing = hashing();
+
+        assert_ok_eq!(
+            hashing.needs_rehash(
+                "$argon2id$v=19$m=65536,t=3,p=2$gZiV/M1gPc22ElAH/Jh1Hw$CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            ),
+            false
+        );
+    }
+}
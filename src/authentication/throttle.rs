@@ -0,0 +1,272 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use super::AuthError;
+use crate::configuration::{FailureThresholds, LoginThrottleSettings};
+
+/// A key locked out by a failed login.
+#[derive(Debug, serde::Serialize)]
+pub struct Lockout {
+    pub key: String,
+    pub failures: i32,
+    pub locked_until: DateTime<Utc>,
+}
+
+/// Slows down and then temporarily locks out repeated failed credential
+/// checks, per username and per client IP.
+pub struct LoginThrottle {
+    failure_window: Duration,
+    lockout: Duration,
+    base_delay: Duration,
+    max_delay: Duration,
+    per_username: FailureThresholds,
+    per_ip: FailureThresholds,
+}
+
+impl LoginThrottle {
+    pub fn new(settings: &LoginThrottleSettings) -> Self {
+        Self {
+            failure_window: settings.failure_window(),
+            lockout: settings.lockout(),
+            base_delay: settings.base_delay(),
+            max_delay: settings.max_delay(),
+            per_username: settings.per_username,
+            per_ip: settings.per_ip,
+        }
+    }
+
+    /// Waits for the delay earned by previous failures, or rejects the
+    /// attempt while one of its keys is locked out.
+    #[tracing::instrument(name = "Throttle login attempt", skip(self, db_pool))]
+    pub async fn before_attempt(
+        &self,
+        username: &str,
+        client_ip: Option<&str>,
+        db_pool: &PgPool,
+    ) -> Result<(), AuthError> {
+        let keys = self.keys(username, client_ip);
+        let key_names: Vec<String> = keys.iter().map(|(key, _)| key.clone()).collect();
+        let now = Utc::now();
+        let window_start = now - self.failure_window;
+
+        let rows = sqlx::query!(
+            r#"SELECT key, failures, last_failure_at, locked_until
+            FROM login_failures WHERE key = ANY($1)"#,
+            &key_names
+        )
+        .fetch_all(db_pool)
+        .await
+        .context("Failed to fetch login failures")?;
+
+        let retry_after = rows
+            .iter()
+            .filter_map(|r| r.locked_until)
+            .filter_map(|locked_until| (locked_until - now).to_std().ok())
+            .max();
+
+        if let Some(retry_after) = retry_after {
+            return Err(AuthError::LockedOut {
+                retry_after: Duration::from_secs(retry_after.as_millis().div_ceil(1000) as u64),
+            });
+        }
+
+        // Lockouts still running were rejected above, failures leading to
+        // one that ended are not held against the next attempts
+        let delay = rows
+            .iter()
+            .filter(|r| r.last_failure_at > window_start && r.locked_until.is_none())
+            .filter_map(|r| {
+                let (_, thresholds) = keys.iter().find(|(key, _)| *key == r.key)?;
+                Some(self.delay_for(r.failures as u32, thresholds))
+            })
+            .max()
+            .unwrap_or_default();
+
+        if !delay.is_zero() {
+            tracing::info!(
+                delay_ms = delay.as_millis() as u64,
+                "Delaying login attempt"
+            );
+            tokio::time::sleep(delay).await;
+        }
+
+        Ok(())
+    }
+
+    /// Counts a failed login, returning the lockouts it starts. Counting
+    /// starts over once the failure window or a lockout has ended.
+    #[tracing::instrument(name = "Record failed login", skip(self, db_pool))]
+    pub async fn record_failure(
+        &self,
+        username: &str,
+        client_ip: Option<&str>,
+        db_pool: &PgPool,
+    ) -> Result<Vec<Lockout>, anyhow::Error> {
+        let mut lockouts = vec![];
+        let now = Utc::now();
+        let window_start = now - self.failure_window;
+
+        for (key, thresholds) in self.keys(username, client_ip) {
+            let failures = sqlx::query_scalar!(
+                r#"INSERT INTO login_failures (key, failures, last_failure_at)
+                VALUES ($1, 1, $2)
+                ON CONFLICT (key) DO UPDATE SET
+                    failures = CASE
+                        WHEN login_failures.last_failure_at < $3
+                            OR login_failures.locked_until <= $2 THEN 1
+                        ELSE login_failures.failures + 1
+                    END,
+                    locked_until = CASE
+                        WHEN login_failures.locked_until <= $2 THEN NULL
+                        ELSE login_failures.locked_until
+                    END,
+                    last_failure_at = EXCLUDED.last_failure_at
+                RETURNING failures"#,
+                key,
+                now,
+                window_start
+            )
+            .fetch_one(db_pool)
+            .await
+            .context("Failed to record login failure")?;
+
+            if failures as u32 >= thresholds.lockout_after {
+                let locked_until = now + self.lockout;
+
+                sqlx::query!(
+                    r#"UPDATE login_failures SET locked_until = $2 WHERE key = $1"#,
+                    key,
+                    locked_until
+                )
+                .execute(db_pool)
+                .await
+                .context("Failed to lock out login")?;
+
+                tracing::warn!(
+                    key,
+                    failures,
+                    %locked_until,
+                    "Locking out after repeated failed logins"
+                );
+                lockouts.push(Lockout {
+                    key,
+                    failures,
+                    locked_until,
+                });
+            }
+        }
+
+        Ok(lockouts)
+    }
+
+    /// Forgets the failures of `username`, returning whether there were
+    /// any. Those of the client IP are kept, one valid account must not
+    /// unlock guessing the others.
+    #[tracing::instrument(name = "Record successful login", skip(self, db_pool))]
+    pub async fn record_success(
+        &self,
+        username: &str,
+        db_pool: &PgPool,
+    ) -> Result<bool, anyhow::Error> {
+        let result = sqlx::query!(
+            r#"DELETE FROM login_failures WHERE key = $1"#,
+            username_key(username)
+        )
+        .execute(db_pool)
+        .await
+        .context("Failed to clear login failures")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Delay doubling with each failure past the threshold, up to `max_delay`.
+    fn delay_for(&self, failures: u32, thresholds: &FailureThresholds) -> Duration {
+        let Some(excess) = failures.checked_sub(thresholds.delay_after) else {
+            return Duration::ZERO;
+        };
+
+        2u32.checked_pow(excess)
+            .and_then(|factor| self.base_delay.checked_mul(factor))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+
+    fn keys(&self, username: &str, client_ip: Option<&str>) -> Vec<(String, FailureThresholds)> {
+        let mut keys = vec![(username_key(username), self.per_username)];
+        if let Some(ip) = client_ip {
+            keys.push((format!("ip:{}", ip), self.per_ip));
+        }
+        keys
+    }
+}
+
+fn username_key(username: &str) -> String {
+    format!("username:{}", username)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::LoginThrottle;
+    use crate::configuration::FailureThresholds;
+
+    fn throttle() -> LoginThrottle {
+        let thresholds = FailureThresholds {
+            delay_after: 3,
+            lockout_after: 10,
+        };
+
+        LoginThrottle {
+            failure_window: Duration::from_secs(900),
+            lockout: Duration::from_secs(900),
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(2),
+            per_username: thresholds,
+            per_ip: thresholds,
+        }
+    }
+
+    #[test]
+    fn no_delay_below_threshold() {
+        let throttle = throttle();
+
+        for failures in 0..3 {
+            assert_eq!(
+                throttle.delay_for(failures, &throttle.per_username),
+                Duration::ZERO
+            );
+        }
+    }
+
+    #[test]
+    fn delay_doubles_with_each_failure_up_to_max() {
+        let throttle = throttle();
+        let delays: Vec<u128> = (3..9)
+            .map(|failures| throttle.delay_for(failures, &throttle.per_username))
+            .map(|delay| delay.as_millis())
+            .collect();
+
+        assert_eq!(delays, vec![250, 500, 1000, 2000, 2000, 2000]);
+        assert_eq!(
+            throttle.delay_for(u32::MAX, &throttle.per_username),
+            Duration::from_secs(2)
+        );
+    }
+
+    #[test]
+    fn client_ip_is_tracked_when_known() {
+        let throttle = throttle();
+
+        let keys: Vec<String> = throttle
+            .keys("bruce", Some("10.0.0.1"))
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec!["username:bruce", "ip:10.0.0.1"]);
+
+        assert_eq!(throttle.keys("bruce", None).len(), 1);
+    }
+}
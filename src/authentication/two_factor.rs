@@ -0,0 +1,332 @@
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use chrono::Utc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng, RngCore};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use totp_rs::{Algorithm, TOTP};
+use uuid::Uuid;
+
+use super::AuthError;
+
+/// Header carrying the TOTP or recovery code along with Basic credentials.
+pub const TOTP_CODE_HEADER: &str = "X-TOTP-Code";
+
+const TOTP_ISSUER: &str = "zero2prod";
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECONDS: u64 = 30;
+/// Codes of the previous and next steps are accepted too, for clock drift.
+const TOTP_SKEW_STEPS: u64 = 1;
+const TOTP_SECRET_BYTES: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+/// Generates a new base32 encoded TOTP secret.
+pub fn generate_totp_secret() -> Secret<String> {
+    let mut secret = [0u8; TOTP_SECRET_BYTES];
+    thread_rng().fill_bytes(&mut secret);
+
+    Secret::new(
+        totp_rs::Secret::Raw(secret.to_vec())
+            .to_encoded()
+            .to_string(),
+    )
+}
+
+/// The `otpauth://` URI authenticator apps are enrolled with, usually
+/// rendered as a QR code.
+pub fn provisioning_uri(secret: &Secret<String>, username: &str) -> Result<String, anyhow::Error> {
+    Ok(totp(secret, username)?.get_url())
+}
+
+/// Returns the time step `code` belongs to if it is valid now and more
+/// recent than `last_step`, so that a code cannot be used twice.
+pub fn verify_totp_code(
+    secret: &Secret<String>,
+    username: &str,
+    code: &str,
+    last_step: Option<i64>,
+) -> Result<Option<i64>, anyhow::Error> {
+    let totp = totp(secret, username)?;
+    let now = u64::try_from(Utc::now().timestamp()).context("System time is before 1970")?;
+
+    let step = matching_step(&totp, code.trim(), now)
+        .map(|step| i64::try_from(step).context("TOTP step out of range"))
+        .transpose()?;
+
+    Ok(step.filter(|step| last_step.is_none_or(|last_step| *step > last_step)))
+}
+
+fn totp(secret: &Secret<String>, username: &str) -> Result<TOTP, anyhow::Error> {
+    let secret = totp_rs::Secret::Encoded(secret.expose_secret().clone())
+        .to_bytes()
+        .context("Invalid TOTP secret")?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW_STEPS as u8,
+        TOTP_STEP_SECONDS,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        username.to_string(),
+    )
+    .context("Invalid TOTP parameters")
+}
+
+fn matching_step(totp: &TOTP, code: &str, now: u64) -> Option<u64> {
+    let current_step = now / TOTP_STEP_SECONDS;
+
+    (current_step.saturating_sub(TOTP_SKEW_STEPS)..=current_step + TOTP_SKEW_STEPS)
+        .find(|step| totp.generate(step * TOTP_STEP_SECONDS) == code)
+}
+
+/// Generates a new set of recovery codes, returned along with their hashes.
+pub fn generate_recovery_codes() -> Vec<(Secret<String>, String)> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let random: String = thread_rng()
+                .sample_iter(&Alphanumeric)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .take(RECOVERY_CODE_LENGTH)
+                .collect();
+            let (head, tail) = random.split_at(RECOVERY_CODE_LENGTH / 2);
+            let code = format!("{}-{}", head, tail);
+            let hash = hash_recovery_code(&code);
+
+            (Secret::new(code), hash)
+        })
+        .collect()
+}
+
+/// Codes are compared without separators or case, as users retype them.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+/// Extracts the code of the `X-TOTP-Code` header, if any.
+pub fn totp_code(headers: &HeaderMap) -> Result<Option<Secret<String>>, anyhow::Error> {
+    headers
+        .get(TOTP_CODE_HEADER)
+        .map(|header_value| {
+            header_value
+                .to_str()
+                .map(|code| Secret::new(code.trim().to_string()))
+                .context("'X-TOTP-Code' header was not a valid utf-8 string")
+        })
+        .transpose()
+}
+
+/// Checks the second factor of a user who passed the password check, a
+/// no-op for users without two-factor authentication.
+#[tracing::instrument(name = "Verify second factor", skip(code, db_pool))]
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    username: &str,
+    code: Option<Secret<String>>,
+    db_pool: &PgPool,
+) -> Result<(), AuthError> {
+    let Some(row) = sqlx::query!(
+        r#"SELECT totp_secret, totp_last_step FROM users
+        WHERE user_id = $1 AND totp_enabled_at IS NOT NULL"#,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to fetch two-factor settings")?
+    else {
+        return Ok(());
+    };
+
+    let secret = Secret::new(
+        row.totp_secret
+            .context("Two-factor authentication is enabled without a secret")?,
+    );
+    let code = code.ok_or(AuthError::SecondFactorRequired)?;
+
+    if let Some(step) =
+        verify_totp_code(&secret, username, code.expose_secret(), row.totp_last_step)?
+    {
+        // Guarded against a concurrent login using the same code
+        let result = sqlx::query!(
+            r#"UPDATE users SET totp_last_step = $2
+            WHERE user_id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)"#,
+            user_id,
+            step
+        )
+        .execute(db_pool)
+        .await
+        .context("Failed to record TOTP step")?;
+
+        if result.rows_affected() == 1 {
+            return Ok(());
+        }
+    }
+
+    if use_recovery_code(user_id, &code, db_pool).await? {
+        tracing::warn!("Authenticated with a recovery code");
+        return Ok(());
+    }
+
+    Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+        "Invalid two-factor code"
+    )))
+}
+
+async fn use_recovery_code(
+    user_id: Uuid,
+    code: &Secret<String>,
+    db_pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE recovery_codes SET used_at = $3
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL"#,
+        user_id,
+        hash_recovery_code(code.expose_secret()),
+        Utc::now()
+    )
+    .execute(db_pool)
+    .await
+    .context("Failed to use recovery code")?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Replaces the recovery codes of `user_id`, returning the new ones.
+#[tracing::instrument(name = "Replace recovery codes", skip(transaction))]
+pub async fn replace_recovery_codes(
+    user_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<Secret<String>>, anyhow::Error> {
+    let (codes, hashes): (Vec<_>, Vec<_>) = generate_recovery_codes().into_iter().unzip();
+
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to delete recovery codes")?;
+
+    sqlx::query!(
+        r#"INSERT INTO recovery_codes (user_id, code_hash)
+        SELECT $1, UNNEST($2::TEXT[])"#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to store recovery codes")?;
+
+    Ok(codes)
+}
+
+/// Turns two-factor authentication off for `user_id`, returning whether it
+/// was enabled or being enrolled.
+#[tracing::instrument(name = "Reset two-factor authentication", skip(transaction))]
+pub async fn reset_two_factor(
+    user_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+        WHERE user_id = $1 AND totp_secret IS NOT NULL"#,
+        user_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to reset two-factor settings")?;
+
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to delete recovery codes")?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use claims::{assert_none, assert_ok, assert_some_eq};
+    use secrecy::{ExposeSecret, Secret};
+
+    use super::{
+        generate_recovery_codes, generate_totp_secret, hash_recovery_code, matching_step,
+        provisioning_uri, totp, totp_code, verify_totp_code, TOTP_STEP_SECONDS,
+    };
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn provisioning_uri_names_the_issuer_and_user() {
+        let secret = generate_totp_secret();
+
+        let uri = assert_ok!(provisioning_uri(&secret, "bruce"));
+
+        assert!(uri.starts_with("otpauth://totp/zero2prod:bruce?"));
+        assert!(uri.contains(&format!("secret={}", secret.expose_secret())));
+    }
+
+    #[test]
+    fn codes_of_adjacent_steps_are_accepted() {
+        let totp = totp(&generate_totp_secret(), "bruce").unwrap();
+        let current_step = NOW / TOTP_STEP_SECONDS;
+
+        for step in [current_step - 1, current_step, current_step + 1] {
+            let code = totp.generate(step * TOTP_STEP_SECONDS);
+            assert_some_eq!(matching_step(&totp, &code, NOW), step);
+        }
+
+        let stale_code = totp.generate((current_step - 2) * TOTP_STEP_SECONDS);
+        assert_none!(matching_step(&totp, &stale_code, NOW));
+    }
+
+    #[test]
+    fn a_code_cannot_be_used_twice() {
+        let secret = generate_totp_secret();
+        let code = totp(&secret, "bruce").unwrap().generate_current().unwrap();
+
+        let step = verify_totp_code(&secret, "bruce", &code, None)
+            .unwrap()
+            .unwrap();
+
+        assert_none!(verify_totp_code(&secret, "bruce", &code, Some(step)).unwrap());
+    }
+
+    #[test]
+    fn recovery_codes_are_unique_and_match_their_hash() {
+        let codes = generate_recovery_codes();
+
+        for (code, hash) in &codes {
+            assert_eq!(hash_recovery_code(code.expose_secret()), *hash);
+            assert_eq!(
+                hash_recovery_code(&code.expose_secret().to_uppercase().replace('-', " ")),
+                *hash
+            );
+        }
+
+        let mut hashes: Vec<_> = codes.iter().map(|(_, hash)| hash).collect();
+        hashes.sort();
+        hashes.dedup();
+        assert_eq!(hashes.len(), codes.len());
+    }
+
+    #[test]
+    fn totp_code_is_read_from_its_header() {
+        let mut headers = HeaderMap::new();
+        assert_none!(totp_code(&headers).unwrap());
+
+        headers.insert(
+            HeaderName::from_static("x-totp-code"),
+            HeaderValue::from_static(" 123456 "),
+        );
+        let code: Secret<String> = totp_code(&headers).unwrap().unwrap();
+
+        assert_eq!(code.expose_secret(), "123456");
+    }
+}
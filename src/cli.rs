@@ -0,0 +1,558 @@
+use std::{
+    io::{BufRead, Read, Write},
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{LoginThrottle, PasswordHashing},
+    configuration::{ConfigurationError, Settings},
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus, UserRole},
+    email_client::EmailClient,
+    routes::{change_subscription_status, insert_subscriber},
+    users::{self, validate_password, NewUser},
+};
+
+const GENERATED_PASSWORD_LENGTH: usize = 24;
+
+#[derive(Parser, Debug)]
+#[command(about = "Newsletter delivery service")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start the server (the default)
+    Serve,
+    /// Apply pending database migrations, then exit
+    Migrate,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    #[command(flatten)]
+    Admin(AdminCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the effective configuration, with secrets redacted, and check
+    /// it
+    Check,
+}
+
+/// Operational tasks run against the configured database and email
+/// provider, then exit.
+#[derive(Subcommand, Debug)]
+pub enum AdminCommand {
+    /// Create a user, printing a generated password unless one is given
+    CreateUser {
+        username: String,
+        #[arg(long, value_parser = |s: &str| UserRole::parse(s.into()), default_value = "editor")]
+        role: UserRole,
+        /// Where password reset links are sent
+        #[arg(long)]
+        email: Option<String>,
+        /// Read the password from the first line of stdin
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Set a new password for a user, printing a generated one unless one
+    /// is given
+    ResetPassword {
+        username: String,
+        /// Read the password from the first line of stdin
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Print subscribers as CSV
+    ListSubscribers {
+        #[arg(long, value_parser = |s: &str| SubscriptionStatus::parse(s.into()))]
+        status: Option<SubscriptionStatus>,
+    },
+    /// Import subscribers from a CSV file with `email` and `name` columns
+    ImportSubscribers {
+        path: PathBuf,
+        /// Status given to the imported subscribers. No confirmation email
+        /// is sent, so only pass `confirmed` for lists whose consent was
+        /// already collected
+        #[arg(long, value_parser = |s: &str| SubscriptionStatus::parse(s.into()))]
+        status: SubscriptionStatus,
+    },
+    /// Send an email to check the email provider settings
+    SendTestEmail { address: String },
+}
+
+/// Tools an admin command may need, built from the configuration.
+pub struct AdminContext<'a> {
+    pub db_pool: &'a PgPool,
+    pub email_client: &'a EmailClient,
+    pub password_hashing: &'a PasswordHashing,
+    pub login_throttle: &'a LoginThrottle,
+}
+
+/// Runs `command`, writing its output to `out`.
+pub async fn run_admin_command(
+    command: AdminCommand,
+    context: AdminContext<'_>,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    match command {
+        AdminCommand::CreateUser {
+            username,
+            role,
+            email,
+            password_stdin,
+        } => {
+            let (password, generated) = read_or_generate_password(password_stdin)?;
+            let user_id = create_user(
+                username.clone(),
+                email,
+                password.clone(),
+                role,
+                context.password_hashing,
+                context.db_pool,
+            )
+            .await?;
+            writeln!(out, "Created {} {} with id {}", role, username, user_id)?;
+            if generated {
+                writeln!(out, "Password: {}", password.expose_secret())?;
+            }
+        }
+        AdminCommand::ResetPassword {
+            username,
+            password_stdin,
+        } => {
+            let (password, generated) = read_or_generate_password(password_stdin)?;
+            reset_password(
+                &username,
+                password.clone(),
+                context.password_hashing,
+                context.login_throttle,
+                context.db_pool,
+            )
+            .await?;
+            writeln!(out, "Reset the password of {}", username)?;
+            if generated {
+                writeln!(out, "Password: {}", password.expose_secret())?;
+            }
+        }
+        AdminCommand::ListSubscribers { status } => {
+            list_subscribers(status, context.db_pool, out).await?;
+        }
+        AdminCommand::ImportSubscribers { path, status } => {
+            let file = std::fs::File::open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            let report = import_subscribers(file, status, context.db_pool).await?;
+            writeln!(
+                out,
+                "Imported {} subscribers, skipped {} already subscribed, rejected {} invalid rows",
+                report.imported,
+                report.already_subscribed,
+                report.invalid.len()
+            )?;
+            for row in &report.invalid {
+                writeln!(out, "line {}: {}", row.line, row.error)?;
+            }
+        }
+        AdminCommand::SendTestEmail { address } => {
+            send_test_email(address, context.email_client).await?;
+            writeln!(out, "Test email sent")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes `settings` as JSON, with secrets redacted, then checks them.
+pub fn check_configuration(settings: &Settings, out: &mut impl Write) -> Result<(), anyhow::Error> {
+    serde_json::to_writer_pretty(&mut *out, settings).context("Failed to print configuration")?;
+    writeln!(out)?;
+
+    settings.validate().map_err(ConfigurationError::Invalid)?;
+
+    Ok(())
+}
+
+fn read_or_generate_password(
+    password_stdin: bool,
+) -> Result<(Secret<String>, bool), anyhow::Error> {
+    if password_stdin {
+        let mut password = String::new();
+        std::io::stdin()
+            .lock()
+            .read_line(&mut password)
+            .context("Failed to read password from stdin")?;
+        let password = password.trim_end_matches(['\r', '\n']).to_string();
+        Ok((Secret::new(password), false))
+    } else {
+        Ok((generate_password(), true))
+    }
+}
+
+fn generate_password() -> Secret<String> {
+    let password = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(GENERATED_PASSWORD_LENGTH)
+        .collect();
+
+    Secret::new(password)
+}
+
+/// Creates a user like `POST /admin/users` does, without an acting user.
+#[tracing::instrument(
+    name = "Create user from the CLI",
+    skip(email, password, password_hashing, db_pool)
+)]
+pub async fn create_user(
+    username: String,
+    email: Option<String>,
+    password: Secret<String>,
+    role: UserRole,
+    password_hashing: &PasswordHashing,
+    db_pool: &PgPool,
+) -> Result<Uuid, anyhow::Error> {
+    let new_user = NewUser::parse(username.clone(), email, password, role).map_err(|errors| {
+        let messages: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+        anyhow::anyhow!("Invalid user: {}", messages.join(", "))
+    })?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    let user_id = users::create_user(new_user, password_hashing, &mut transaction).await?;
+
+    AuditEvent::new(AuditAction::UserCreated)
+        .on(user_id)
+        .with_details(serde_json::json!({ "username": username, "role": role, "via": "cli" }))
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    tracing::info!(%user_id, "Created user");
+
+    Ok(user_id)
+}
+
+/// Sets the password of `username`, invalidating pending reset links and
+/// lifting any login lockout.
+#[tracing::instrument(
+    name = "Reset password from the CLI",
+    skip(password, password_hashing, login_throttle, db_pool)
+)]
+pub async fn reset_password(
+    username: &str,
+    password: Secret<String>,
+    password_hashing: &PasswordHashing,
+    login_throttle: &LoginThrottle,
+    db_pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    validate_password(&password).map_err(|e| anyhow::anyhow!(e.message))?;
+    let password_hash = password_hashing.hash(password).await?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    let user_id = sqlx::query_scalar!(
+        r#"UPDATE users SET password_hash = $2 WHERE username = $1 RETURNING user_id"#,
+        username,
+        password_hash.expose_secret()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to update password")?
+    .with_context(|| format!("No user named {}", username))?;
+
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete password reset tokens")?;
+
+    AuditEvent::new(AuditAction::PasswordReset)
+        .on(user_id)
+        .with_details(serde_json::json!({ "via": "cli" }))
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    login_throttle.record_success(username, db_pool).await?;
+
+    tracing::info!(%user_id, "Password reset");
+
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberRow {
+    email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+}
+
+/// Writes the subscribers, oldest first, as CSV with a header row.
+#[tracing::instrument(name = "List subscribers from the CLI", skip(db_pool, out))]
+pub async fn list_subscribers(
+    status: Option<SubscriptionStatus>,
+    db_pool: &PgPool,
+    out: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    let subscribers = sqlx::query_as!(
+        SubscriberRow,
+        r#"
+    SELECT display_email AS email, name, status AS "status: SubscriptionStatus", subscribed_at
+    FROM subscriptions
+    WHERE ($1::subscription_status IS NULL OR status = $1)
+    ORDER BY subscribed_at, id
+    "#,
+        status as Option<SubscriptionStatus>
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch subscribers")?;
+
+    let mut writer = csv::Writer::from_writer(out);
+    for subscriber in subscribers {
+        writer
+            .serialize(subscriber)
+            .context("Failed to write subscriber")?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+struct ImportedSubscriber {
+    email: String,
+    name: String,
+}
+
+impl TryFrom<ImportedSubscriber> for NewSubscriber {
+    type Error = String;
+
+    fn try_from(value: ImportedSubscriber) -> Result<Self, Self::Error> {
+        Ok(Self {
+            email: SubscriberEmail::parse(value.email)?,
+            name: SubscriberName::parse(value.name)?,
+        })
+    }
+}
+
+/// A row of an import that was not imported.
+#[derive(Debug)]
+pub struct InvalidRow {
+    pub line: u64,
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: u64,
+    pub already_subscribed: u64,
+    pub invalid: Vec<InvalidRow>,
+}
+
+/// Adds the subscribers of a CSV document with `email` and `name` columns,
+/// skipping invalid rows and addresses already subscribed.
+///
+/// No confirmation email is sent, the subscribers are given `status`
+/// directly, so only import lists whose consent was already collected.
+#[tracing::instrument(name = "Import subscribers", skip(csv, db_pool))]
+pub async fn import_subscribers(
+    csv: impl Read,
+    status: SubscriptionStatus,
+    db_pool: &PgPool,
+) -> Result<ImportReport, anyhow::Error> {
+    let mut report = ImportReport::default();
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv);
+
+    let headers = reader
+        .headers()
+        .context("Failed to read CSV header")?
+        .clone();
+    let mut record = csv::StringRecord::new();
+    loop {
+        let line = reader.position().line();
+        let new_subscriber = match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => record
+                .deserialize::<ImportedSubscriber>(Some(&headers))
+                .map_err(|e| e.to_string())
+                .and_then(NewSubscriber::try_from),
+            Err(e) => Err(e.to_string()),
+        };
+        let new_subscriber = match new_subscriber {
+            Ok(new_subscriber) => new_subscriber,
+            Err(error) => {
+                report.invalid.push(InvalidRow { line, error });
+                continue;
+            }
+        };
+
+        if import_subscriber(&new_subscriber, status, db_pool).await? {
+            report.imported += 1;
+        } else {
+            report.already_subscribed += 1;
+        }
+    }
+
+    AuditEvent::new(AuditAction::SubscribersImported)
+        .with_details(serde_json::json!({
+            "status": status,
+            "imported": report.imported,
+            "already_subscribed": report.already_subscribed,
+            "invalid": report.invalid.len(),
+        }))
+        .record(db_pool)
+        .await?;
+
+    tracing::info!(
+        imported = report.imported,
+        already_subscribed = report.already_subscribed,
+        invalid = report.invalid.len(),
+        "Imported subscribers"
+    );
+
+    Ok(report)
+}
+
+/// Returns false when the address is already subscribed.
+async fn import_subscriber(
+    new_subscriber: &NewSubscriber,
+    status: SubscriptionStatus,
+    db_pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    let Some(subscriber_id) = insert_subscriber(new_subscriber, &mut transaction)
+        .await
+        .context("Failed to insert subscriber")?
+    else {
+        return Ok(false);
+    };
+    change_subscription_status(&mut transaction, subscriber_id, status)
+        .await
+        .context("Failed to set subscription status")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Send test email", skip(email_client))]
+pub async fn send_test_email(
+    address: String,
+    email_client: &EmailClient,
+) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(address).map_err(anyhow::Error::msg)?;
+
+    email_client
+        .send_email(
+            &recipient,
+            "Test email",
+            "<p>Your email settings work.</p>",
+            "Your email settings work.",
+        )
+        .await
+        .context("Failed to send test email")
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::{AdminCommand, Cli, Command};
+    use crate::domain::{SubscriptionStatus, UserRole};
+
+    #[test]
+    fn server_is_started_without_a_subcommand() {
+        let cli = Cli::try_parse_from(["zero2prod"]).unwrap();
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn admin_commands_are_top_level_subcommands() {
+        let cli =
+            Cli::try_parse_from(["zero2prod", "create-user", "bruce", "--role", "admin"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Admin(AdminCommand::CreateUser {
+                role: UserRole::Admin,
+                password_stdin: false,
+                ..
+            }))
+        ));
+
+        let cli = Cli::try_parse_from([
+            "zero2prod",
+            "import-subscribers",
+            "list.csv",
+            "--status",
+            "pending_confirmation",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Admin(AdminCommand::ImportSubscribers {
+                status: SubscriptionStatus::PendingConfirmation,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn imported_subscribers_are_not_confirmed_by_default() {
+        let error =
+            Cli::try_parse_from(["zero2prod", "import-subscribers", "list.csv"]).unwrap_err();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(
+            Cli::try_parse_from(["zero2prod", "create-user", "bruce", "--role", "owner"]).is_err()
+        );
+        assert!(
+            Cli::try_parse_from(["zero2prod", "list-subscribers", "--status", "gone"]).is_err()
+        );
+    }
+}
@@ -0,0 +1,56 @@
+use std::{convert::Infallible, future::Ready, net::IpAddr};
+
+use actix_web::{
+    dev::Payload,
+    http::header::{HeaderMap, X_FORWARDED_FOR},
+    web, FromRequest, HttpRequest,
+};
+
+/// Whether the client address can be taken from `X-Forwarded-For`, only safe
+/// behind a proxy that appends the address it received the request from.
+pub struct TrustForwardedFor(pub bool);
+
+/// Address of the client that sent the request, if it is known.
+pub struct ClientIp(pub Option<String>);
+
+impl ClientIp {
+    pub fn of(req: &HttpRequest) -> Self {
+        let trust_forwarded_for = req
+            .app_data::<web::Data<TrustForwardedFor>>()
+            .is_some_and(|trust| trust.0);
+
+        let forwarded_for = if trust_forwarded_for {
+            last_forwarded_for(req.headers())
+        } else {
+            None
+        };
+        let ip = forwarded_for.or_else(|| req.peer_addr().map(|addr| addr.ip()));
+
+        Self(ip.map(|ip| ip.to_string()))
+    }
+}
+
+/// The address appended to `X-Forwarded-For` by the proxy in front of us.
+/// Entries to its left were sent by the client, which can put anything
+/// there.
+fn last_forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .last()?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+impl FromRequest for ClientIp {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        std::future::ready(Ok(Self::of(req)))
+    }
+}
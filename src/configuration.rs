@@ -1,44 +1,114 @@
+use std::{collections::BTreeMap, str::FromStr};
+
 use secrecy::{ExposeSecret, Secret};
 use serde;
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::{domain::SubscriberEmail, users::NewUser};
+
+/// A setting with an invalid value, identified by its key path, e.g.
+/// `email.sender`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    pub key: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigurationError {
+    #[error("APP_ENV: {0}")]
+    InvalidEnvironment(String),
+
+    #[error("Failed to load configuration")]
+    LoadError(#[from] config::ConfigError),
 
-use crate::domain::SubscriberEmail;
+    #[error("Invalid configuration:{}", .0.iter().map(|p| format!("\n  {}", p)).collect::<String>())]
+    Invalid(Vec<ConfigProblem>),
+}
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email: EmailSettings,
+    pub deliverability: DeliverabilitySettings,
+    pub rate_limit: RateLimitSettings,
+    pub login_throttle: LoginThrottleSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub metrics: MetricsSettings,
+    pub tracing: TracingSettings,
+    pub logging: LoggingSettings,
+    pub health: HealthSettings,
+    /// Admin created on start when there is no user yet, e.g. from
+    /// `APP_BOOTSTRAP_ADMIN__USERNAME` and `APP_BOOTSTRAP_ADMIN__PASSWORD`.
+    pub bootstrap_admin: Option<BootstrapAdminSettings>,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct ApplicationSettings {
     pub host: String,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub base_url: String,
+    /// Take the client IP from the last `X-Forwarded-For` entry, only safe
+    /// behind a proxy that appends the address it received the request from.
+    pub trust_forwarded_for: bool,
+    /// How long in-flight requests and background work get to finish once
+    /// a shutdown signal is received.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_grace_period_seconds: u64,
+}
+
+impl ApplicationSettings {
+    pub fn shutdown_grace_period(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.shutdown_grace_period_seconds)
+    }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct DatabaseSettings {
     pub username: String,
+    #[serde(serialize_with = "redact")]
     pub password: Secret<String>,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
     pub database_name: String,
     pub require_ssl: bool,
+    /// Apply pending migrations when the application starts, instead of
+    /// with the `migrate` subcommand.
+    pub run_migrations_on_startup: bool,
 }
 
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
+/// Loads the configuration and checks it, reporting every invalid setting
+/// at once.
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    let settings = load_configuration()?;
+    settings.validate().map_err(ConfigurationError::Invalid)?;
+
+    Ok(settings)
+}
+
+/// Loads the configuration of the `APP_ENV` environment without checking
+/// it.
+pub fn load_configuration() -> Result<Settings, ConfigurationError> {
     let base_path = std::env::current_dir().expect("Failed to determine current directory");
     let configuration_dir = base_path.join("configuration");
 
     let app_env: Environment = std::env::var("APP_ENV")
         .unwrap_or_else(|_| "local".into())
         .try_into()
-        .expect("Failed to parse APP_ENV");
+        .map_err(ConfigurationError::InvalidEnvironment)?;
 
     let env_filename = format!("{}.yaml", app_env.as_str());
 
@@ -52,7 +122,170 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         )
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    Ok(settings.try_deserialize::<Settings>()?)
+}
+
+/// Hides secrets when printing the configuration.
+fn redact<S: serde::Serializer>(_: &Secret<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("[REDACTED]")
+}
+
+/// Collects the problems found while checking settings.
+#[derive(Default)]
+struct Problems(Vec<ConfigProblem>);
+
+impl Problems {
+    fn push(&mut self, key: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigProblem {
+            key: key.into(),
+            message: message.into(),
+        });
+    }
+
+    fn check(&mut self, is_valid: bool, key: impl Into<String>, message: &str) {
+        if !is_valid {
+            self.push(key, message);
+        }
+    }
+
+    fn non_zero(&mut self, key: impl Into<String>, value: impl Into<u64>) {
+        self.check(value.into() != 0, key, "must be greater than 0");
+    }
+
+    fn url(&mut self, key: &str, value: &str) {
+        match reqwest::Url::parse(value) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            Ok(_) => self.push(key, format!("{} is not an http(s) URL", value)),
+            Err(e) => self.push(key, format!("{} is not a valid URL: {}", value, e)),
+        }
+    }
+
+    fn log_level(&mut self, key: impl Into<String>, value: &str) {
+        if LevelFilter::from_str(value).is_err() {
+            self.push(
+                key,
+                format!(
+                    "{} is not a log level, use one of trace, debug, info, warn, error or off",
+                    value
+                ),
+            );
+        }
+    }
+}
+
+impl Settings {
+    /// Checks the settings that deserialization alone cannot, returning
+    /// every problem found.
+    pub fn validate(&self) -> Result<(), Vec<ConfigProblem>> {
+        let mut problems = Problems::default();
+
+        problems.url("application.base_url", &self.application.base_url);
+
+        problems.check(
+            !self.database.host.is_empty(),
+            "database.host",
+            "must not be empty",
+        );
+        problems.non_zero("database.port", self.database.port);
+
+        problems.url("email.base_url", &self.email.base_url);
+        if let Err(e) = SubscriberEmail::parse(self.email.sender.clone()) {
+            problems.push("email.sender", e);
+        }
+        problems.non_zero(
+            "email.timeout_milliseconds",
+            self.email.timeout_milliseconds,
+        );
+
+        for (key, bucket) in [
+            ("rate_limit.per_ip", &self.rate_limit.per_ip),
+            ("rate_limit.per_email", &self.rate_limit.per_email),
+        ] {
+            problems.non_zero(format!("{}.capacity", key), bucket.capacity);
+            problems.non_zero(
+                format!("{}.refill_every_seconds", key),
+                bucket.refill_every_seconds,
+            );
+        }
+
+        let throttle = &self.login_throttle;
+        problems.non_zero(
+            "login_throttle.failure_window_seconds",
+            throttle.failure_window_seconds,
+        );
+        problems.non_zero("login_throttle.lockout_seconds", throttle.lockout_seconds);
+        problems.check(
+            throttle.base_delay_milliseconds <= throttle.max_delay_milliseconds,
+            "login_throttle.max_delay_milliseconds",
+            "must be at least base_delay_milliseconds",
+        );
+
+        let hashing = &self.password_hashing;
+        if let Err(e) = argon2::Params::new(
+            hashing.memory_kib,
+            hashing.iterations,
+            hashing.parallelism,
+            None,
+        ) {
+            problems.push(
+                "password_hashing",
+                format!("invalid Argon2 parameters: {}", e),
+            );
+        }
+
+        match self.metrics.port {
+            Some(0) => problems.push("metrics.port", "must be greater than 0"),
+            Some(port) if port == self.application.port => {
+                problems.push("metrics.port", "must differ from application.port")
+            }
+            _ => {}
+        }
+
+        problems.non_zero(
+            "health.timeout_milliseconds",
+            self.health.timeout_milliseconds,
+        );
+
+        if self.tracing.otlp_enabled {
+            problems.url("tracing.otlp_endpoint", &self.tracing.otlp_endpoint);
+        }
+        problems.check(
+            !self.tracing.service_name.is_empty(),
+            "tracing.service_name",
+            "must not be empty",
+        );
+
+        problems.log_level("logging.level", &self.logging.level);
+        for (module, level) in &self.logging.modules {
+            problems.log_level(format!("logging.modules.{}", module), level);
+        }
+        if let Some(file) = &self.logging.file {
+            problems.check(
+                !file.directory.is_empty(),
+                "logging.file.directory",
+                "must not be empty",
+            );
+        }
+
+        if let Some(admin) = &self.bootstrap_admin {
+            if let Err(errors) = NewUser::parse(
+                admin.username.clone(),
+                None,
+                admin.password.clone(),
+                crate::domain::UserRole::Admin,
+            ) {
+                for e in errors {
+                    problems.push(format!("bootstrap_admin.{}", e.field), e.message);
+                }
+            }
+        }
+
+        if problems.0.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.0)
+        }
+    }
 }
 
 impl DatabaseSettings {
@@ -97,15 +330,19 @@ impl TryFrom<String> for Environment {
         match s.to_lowercase().as_str() {
             "local" => Ok(Self::Local),
             "production" => Ok(Self::Production),
-            other => Err(format!("{} is not supported environment", other)),
+            other => Err(format!(
+                "{} is not a supported environment, use either local or production",
+                other
+            )),
         }
     }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct EmailSettings {
     pub base_url: String,
     sender: String,
+    #[serde(serialize_with = "redact")]
     pub authorization_token: Secret<String>,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     timeout_milliseconds: u64,
@@ -113,10 +350,283 @@ pub struct EmailSettings {
 
 impl EmailSettings {
     pub fn sender(&self) -> SubscriberEmail {
-        SubscriberEmail::parse(String::from(&self.sender)).expect("Unable to parse sender email")
+        SubscriberEmail::parse(String::from(&self.sender))
+            .expect("The sender is checked by Settings::validate")
     }
 
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
 }
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct DeliverabilitySettings {
+    pub disposable_domains: Vec<String>,
+    pub reject_role_addresses: bool,
+    pub check_mx_records: bool,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct RateLimitSettings {
+    pub enabled: bool,
+    pub store: RateLimitStoreKind,
+    pub per_ip: BucketSettings,
+    pub per_email: BucketSettings,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStoreKind {
+    Memory,
+    Postgres,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct BucketSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    refill_every_seconds: u64,
+}
+
+impl BucketSettings {
+    pub fn refill_every(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.refill_every_seconds)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct LoginThrottleSettings {
+    /// Failures older than this are forgotten.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_window_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+    pub per_username: FailureThresholds,
+    pub per_ip: FailureThresholds,
+}
+
+impl LoginThrottleSettings {
+    pub fn failure_window(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.failure_window_seconds)
+    }
+
+    pub fn lockout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lockout_seconds)
+    }
+
+    pub fn base_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.base_delay_milliseconds)
+    }
+
+    pub fn max_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.max_delay_milliseconds)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy)]
+pub struct FailureThresholds {
+    /// Failed attempts after which each new attempt is delayed.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub delay_after: u32,
+    /// Failed attempts after which attempts are rejected for a while.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_after: u32,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct BootstrapAdminSettings {
+    pub username: String,
+    #[serde(serialize_with = "redact")]
+    pub password: Secret<String>,
+}
+
+/// Argon2id parameters for new password hashes.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct PasswordHashingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct MetricsSettings {
+    /// Serve `GET /metrics` in the Prometheus text format.
+    pub enabled: bool,
+    /// Serve metrics, without authentication, on this port instead of the
+    /// application one. On the application port they require a user
+    /// allowed to view stats.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub port: Option<u16>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct HealthSettings {
+    /// Time allowed to each readiness check.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    /// How long a readiness outcome is reused before checking again.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cache_seconds: u64,
+    pub check_email_provider: bool,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct TracingSettings {
+    /// Export spans to an OpenTelemetry collector over OTLP/HTTP.
+    pub otlp_enabled: bool,
+    /// Full URL of the collector traces endpoint, usually ending in `/v1/traces`.
+    pub otlp_endpoint: String,
+    pub otlp_protocol: OtlpProtocol,
+    /// `service.name` of the exported spans.
+    pub service_name: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OtlpProtocol {
+    Protobuf,
+    Json,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct LoggingSettings {
+    pub format: LogFormat,
+    /// Default level, e.g. `info`. `RUST_LOG` takes precedence when set.
+    pub level: String,
+    /// Level overrides by module path, e.g. `sqlx: warn`.
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+    /// Also write logs to rotated files.
+    pub file: Option<LogFileSettings>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Bunyan JSON, for log aggregators.
+    Bunyan,
+    /// Multi-line human output, for a developer terminal.
+    Pretty,
+    /// Compact `key=value` lines.
+    Logfmt,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct LogFileSettings {
+    pub directory: String,
+    /// Log files are named `<prefix>.<date>`.
+    pub prefix: String,
+    pub rotation: LogRotation,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::{load_configuration, BootstrapAdminSettings, Settings};
+
+    fn settings() -> Settings {
+        load_configuration().expect("Failed to load configuration")
+    }
+
+    fn invalid_keys(settings: &Settings) -> Vec<String> {
+        settings
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|problem| problem.key)
+            .collect()
+    }
+
+    #[test]
+    fn checked_in_configuration_is_valid() {
+        assert_eq!(settings().validate(), Ok(()));
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_key() {
+        let mut settings = settings();
+        settings.application.base_url = "localhost".into();
+        settings.email.base_url = "ftp://mail.example.com".into();
+        settings.email.sender = "not-an-email".into();
+        settings.email.timeout_milliseconds = 0;
+        settings.rate_limit.per_email.capacity = 0;
+        settings.login_throttle.base_delay_milliseconds = 10_000;
+        settings.login_throttle.max_delay_milliseconds = 1_000;
+        settings.metrics.port = Some(0);
+        settings
+            .logging
+            .modules
+            .insert("sqlx".into(), "loud".into());
+
+        assert_eq!(
+            invalid_keys(&settings),
+            vec![
+                "application.base_url",
+                "email.base_url",
+                "email.sender",
+                "email.timeout_milliseconds",
+                "rate_limit.per_email.capacity",
+                "login_throttle.max_delay_milliseconds",
+                "metrics.port",
+                "logging.modules.sqlx",
+            ]
+        );
+    }
+
+    #[test]
+    fn otlp_endpoint_is_only_checked_when_exporting() {
+        let mut settings = settings();
+        settings.tracing.otlp_endpoint = "collector".into();
+        assert_eq!(settings.validate(), Ok(()));
+
+        settings.tracing.otlp_enabled = true;
+        assert_eq!(invalid_keys(&settings), vec!["tracing.otlp_endpoint"]);
+    }
+
+    #[test]
+    fn bootstrap_admin_is_checked_like_any_new_user() {
+        let mut settings = settings();
+        settings.bootstrap_admin = Some(BootstrapAdminSettings {
+            username: "bruce wayne".into(),
+            password: Secret::new("short".into()),
+        });
+
+        assert_eq!(
+            invalid_keys(&settings),
+            vec!["bootstrap_admin.username", "bootstrap_admin.password"]
+        );
+    }
+
+    #[test]
+    fn secrets_are_redacted_when_printed() {
+        let mut settings = settings();
+        settings.bootstrap_admin = Some(BootstrapAdminSettings {
+            username: "admin".into(),
+            password: Secret::new("admin-secret-password".into()),
+        });
+
+        let printed = serde_json::to_value(&settings).unwrap();
+
+        assert_eq!(printed["database"]["password"], "[REDACTED]");
+        assert_eq!(printed["email"]["authorization_token"], "[REDACTED]");
+        assert_eq!(printed["bootstrap_admin"]["password"], "[REDACTED]");
+        assert_eq!(printed["bootstrap_admin"]["username"], "admin");
+    }
+}
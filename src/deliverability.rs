@@ -0,0 +1,230 @@
+use std::{collections::HashSet, sync::Arc};
+
+use hickory_resolver::{error::ResolveErrorKind, TokioAsyncResolver};
+
+use crate::domain::SubscriberEmail;
+
+/// Local parts that identify a mailbox for a function rather than a person.
+const ROLE_LOCAL_PARTS: [&str; 9] = [
+    "abuse",
+    "donotreply",
+    "hostmaster",
+    "mailer-daemon",
+    "no-reply",
+    "noreply",
+    "postmaster",
+    "root",
+    "webmaster",
+];
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum UndeliverableEmail {
+    #[error("{0} is a disposable email domain")]
+    DisposableDomain(String),
+
+    #[error("{0} is a role address, please subscribe with a personal address")]
+    RoleAddress(String),
+
+    #[error("{0} does not accept email")]
+    NoMxRecords(String),
+}
+
+/// Looks up whether a domain publishes MX records.
+#[async_trait::async_trait]
+pub trait MxResolver: Send + Sync {
+    async fn has_mx_records(&self, domain: &str) -> Result<bool, anyhow::Error>;
+}
+
+/// Resolves MX records through the system DNS configuration.
+pub struct DnsMxResolver(TokioAsyncResolver);
+
+impl DnsMxResolver {
+    pub fn from_system_conf() -> Result<Self, anyhow::Error> {
+        Ok(Self(TokioAsyncResolver::tokio_from_system_conf()?))
+    }
+}
+
+#[async_trait::async_trait]
+impl MxResolver for DnsMxResolver {
+    async fn has_mx_records(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        // Trailing dot so the lookup is not expanded with search domains
+        match self.0.mx_lookup(format!("{}.", domain)).await {
+            Ok(lookup) => Ok(lookup.iter().next().is_some()),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Resolver answering from a fixed set of domains, for tests.
+#[derive(Default)]
+pub struct InMemoryMxResolver {
+    domains: HashSet<String>,
+}
+
+impl InMemoryMxResolver {
+    pub fn new<I, S>(domains: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            domains: domains.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl MxResolver for InMemoryMxResolver {
+    async fn has_mx_records(&self, domain: &str) -> Result<bool, anyhow::Error> {
+        Ok(self.domains.contains(domain))
+    }
+}
+
+/// Signup-time checks that an address is likely to belong to a person who
+/// can receive our emails.
+pub struct DeliverabilityChecker {
+    disposable_domains: HashSet<String>,
+    reject_role_addresses: bool,
+    mx_resolver: Option<Arc<dyn MxResolver>>,
+}
+
+impl DeliverabilityChecker {
+    pub fn new(
+        disposable_domains: &[String],
+        reject_role_addresses: bool,
+        mx_resolver: Option<Arc<dyn MxResolver>>,
+    ) -> Self {
+        Self {
+            disposable_domains: disposable_domains
+                .iter()
+                .map(|d| d.trim().to_lowercase())
+                .collect(),
+            reject_role_addresses,
+            mx_resolver,
+        }
+    }
+
+    #[tracing::instrument(name = "Check email deliverability", skip(self))]
+    pub async fn check(&self, email: &SubscriberEmail) -> Result<(), UndeliverableEmail> {
+        let Some((local_part, domain)) = email.as_ref().rsplit_once('@') else {
+            return Ok(());
+        };
+
+        if self.is_disposable(domain) {
+            return Err(UndeliverableEmail::DisposableDomain(domain.to_string()));
+        }
+
+        if self.reject_role_addresses && is_role_address(local_part) {
+            return Err(UndeliverableEmail::RoleAddress(
+                email.display_form().to_string(),
+            ));
+        }
+
+        if let Some(resolver) = &self.mx_resolver {
+            match resolver.has_mx_records(domain).await {
+                Ok(true) => {}
+                Ok(false) => return Err(UndeliverableEmail::NoMxRecords(domain.to_string())),
+                // A DNS outage should not block signups
+                Err(e) => {
+                    tracing::warn!(error.cause_chain = ?e, "Failed to look up MX records, skipping check")
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_disposable(&self, domain: &str) -> bool {
+        // Also match subdomains, e.g. `x.mailinator.com`
+        std::iter::successors(Some(domain), |d| {
+            d.split_once('.').map(|(_, parent)| parent)
+        })
+        .any(|d| self.disposable_domains.contains(d))
+    }
+}
+
+fn is_role_address(local_part: &str) -> bool {
+    // Ignore sub-addressing, `noreply+news@` is still `noreply@`
+    let local_part = local_part
+        .split_once('+')
+        .map_or(local_part, |(base, _)| base)
+        .to_lowercase();
+
+    ROLE_LOCAL_PARTS.contains(&local_part.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use claims::{assert_err_eq, assert_ok};
+
+    use super::{DeliverabilityChecker, InMemoryMxResolver, UndeliverableEmail};
+    use crate::domain::SubscriberEmail;
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    fn checker(mx_domains: Option<&[&str]>) -> DeliverabilityChecker {
+        DeliverabilityChecker::new(
+            &["mailinator.com".to_string()],
+            true,
+            mx_domains.map(|d| Arc::new(InMemoryMxResolver::new(d.iter().copied())) as _),
+        )
+    }
+
+    #[tokio::test]
+    async fn regular_address_is_accepted() {
+        assert_ok!(checker(None).check(&email("bruce@wayne.com")).await);
+    }
+
+    #[tokio::test]
+    async fn disposable_domain_and_its_subdomains_are_rejected() {
+        let checker = checker(None);
+
+        for address in ["bruce@mailinator.com", "bruce@eu.Mailinator.com"] {
+            assert_err_eq!(
+                checker.check(&email(address)).await,
+                UndeliverableEmail::DisposableDomain(
+                    address.split_once('@').unwrap().1.to_lowercase()
+                )
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn role_addresses_are_rejected() {
+        let checker = checker(None);
+
+        for address in [
+            "postmaster@wayne.com",
+            "NoReply@wayne.com",
+            "noreply+news@wayne.com",
+        ] {
+            assert_err_eq!(
+                checker.check(&email(address)).await,
+                UndeliverableEmail::RoleAddress(address.to_string())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn role_addresses_are_accepted_when_check_is_disabled() {
+        let checker = DeliverabilityChecker::new(&[], false, None);
+
+        assert_ok!(checker.check(&email("postmaster@wayne.com")).await);
+    }
+
+    #[tokio::test]
+    async fn domain_without_mx_records_is_rejected() {
+        let checker = checker(Some(&["wayne.com"]));
+
+        assert_ok!(checker.check(&email("bruce@wayne.com")).await);
+        assert_err_eq!(
+            checker.check(&email("bruce@wayne.invalid")).await,
+            UndeliverableEmail::NoMxRecords("wayne.invalid".into())
+        );
+    }
+}
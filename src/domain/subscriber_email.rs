@@ -1,33 +1,58 @@
 use validator::ValidateEmail;
 
+/// A syntactically valid email address.
+///
+/// The address is kept in two forms: the normalised one (trimmed, with a
+/// lowercase ASCII/punycode domain) used for storage, uniqueness and
+/// delivery, and the form the subscriber typed in, used for display.
 #[derive(Debug)]
-pub struct SubscriberEmail(String);
+pub struct SubscriberEmail {
+    normalized: String,
+    display: String,
+}
 
 impl SubscriberEmail {
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if ValidateEmail::validate_email(&s) {
-            Ok(Self(s))
-        } else {
-            Err(format!("{} is not a valid subscriber email", s))
+        let display = s.trim().to_string();
+
+        let normalized = display
+            .rsplit_once('@')
+            .and_then(|(local, domain)| {
+                let domain = idna::domain_to_ascii(domain).ok()?;
+                Some(format!("{}@{}", local, domain))
+            })
+            .filter(ValidateEmail::validate_email);
+
+        match normalized {
+            Some(normalized) => Ok(Self {
+                normalized,
+                display,
+            }),
+            None => Err(format!("{} is not a valid subscriber email", s)),
         }
     }
+
+    /// The address as entered by the subscriber, only trimmed.
+    pub fn display_form(&self) -> &str {
+        &self.display
+    }
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.normalized
     }
 }
 
 impl std::fmt::Display for SubscriberEmail {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.normalized.fmt(f)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use claims::assert_err;
+    use claims::{assert_err, assert_ok};
     use fake::{faker::internet::en::SafeEmail, Fake};
     use rand::{rngs::StdRng, SeedableRng};
 
@@ -73,4 +98,38 @@ mod tests {
         let email = String::from("bruce@");
         assert_err!(SubscriberEmail::parse(email));
     }
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email = SubscriberEmail::parse("  bruce@wayne.com\t".into()).unwrap();
+        assert_eq!(email.as_ref(), "bruce@wayne.com");
+        assert_eq!(email.display_form(), "bruce@wayne.com");
+    }
+
+    #[test]
+    fn domain_is_lowercased_and_local_part_preserved() {
+        let email = SubscriberEmail::parse("Bruce.Wayne@Wayne.COM".into()).unwrap();
+        assert_eq!(email.as_ref(), "Bruce.Wayne@wayne.com");
+        assert_eq!(email.display_form(), "Bruce.Wayne@Wayne.COM");
+    }
+
+    #[test]
+    fn internationalised_domain_is_converted_to_punycode() {
+        let email = SubscriberEmail::parse("bruce@Bücher.de".into()).unwrap();
+        assert_eq!(email.as_ref(), "bruce@xn--bcher-kva.de");
+        assert_eq!(email.display_form(), "bruce@Bücher.de");
+    }
+
+    #[test]
+    fn normalisation_is_idempotent() {
+        let email = SubscriberEmail::parse("Bruce@Bücher.DE".into()).unwrap();
+        let renormalized = SubscriberEmail::parse(email.as_ref().to_string()).unwrap();
+        assert_eq!(email.as_ref(), renormalized.as_ref());
+    }
+
+    #[test]
+    fn email_with_invalid_domain_is_rejected() {
+        assert_err!(SubscriberEmail::parse("bruce@wayne..com".into()));
+        assert_ok!(SubscriberEmail::parse("bruce@wayne.com".into()));
+    }
 }
@@ -0,0 +1,115 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE", try_from = "String")]
+#[sqlx(type_name = "subscription_status", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl SubscriptionStatus {
+    pub fn parse(s: String) -> Result<SubscriptionStatus, String> {
+        match s.to_uppercase().as_str() {
+            "PENDING_CONFIRMATION" => Ok(Self::PendingConfirmation),
+            "CONFIRMED" => Ok(Self::Confirmed),
+            "UNSUBSCRIBED" => Ok(Self::Unsubscribed),
+            _ => Err(format!("{} is not a valid subscription status", s)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "PENDING_CONFIRMATION",
+            SubscriptionStatus::Confirmed => "CONFIRMED",
+            SubscriptionStatus::Unsubscribed => "UNSUBSCRIBED",
+        }
+    }
+
+    /// Checks that a subscription may move from this status to `next`.
+    ///
+    /// Staying in the same status is always allowed, so repeated requests
+    /// (e.g. clicking a confirmation link twice) are idempotent.
+    pub fn transition_to(self, next: SubscriptionStatus) -> Result<SubscriptionStatus, String> {
+        use SubscriptionStatus::*;
+
+        match (self, next) {
+            (current, next) if current == next => Ok(next),
+            (PendingConfirmation, Confirmed)
+            | (PendingConfirmation, Unsubscribed)
+            | (Confirmed, Unsubscribed)
+            | (Unsubscribed, PendingConfirmation) => Ok(next),
+            (current, next) => Err(format!(
+                "Cannot change subscription status from {} to {}",
+                current, next
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for SubscriptionStatus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::parse(s)
+    }
+}
+
+impl std::fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriptionStatus;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn known_statuses_are_parsed() {
+        for status in [
+            SubscriptionStatus::PendingConfirmation,
+            SubscriptionStatus::Confirmed,
+            SubscriptionStatus::Unsubscribed,
+        ] {
+            assert_ok_eq!(SubscriptionStatus::parse(status.as_str().into()), status);
+        }
+    }
+
+    #[test]
+    fn status_parsing_is_case_insensitive() {
+        assert_ok_eq!(
+            SubscriptionStatus::parse("confirmed".into()),
+            SubscriptionStatus::Confirmed
+        );
+    }
+
+    #[test]
+    fn unknown_status_is_rejected() {
+        assert_err!(SubscriptionStatus::parse("ACTIVE".into()));
+    }
+
+    #[test]
+    fn legal_transitions_are_accepted() {
+        use SubscriptionStatus::*;
+
+        for (from, to) in [
+            (PendingConfirmation, Confirmed),
+            (PendingConfirmation, Unsubscribed),
+            (Confirmed, Unsubscribed),
+            (Unsubscribed, PendingConfirmation),
+            (Confirmed, Confirmed),
+        ] {
+            assert_ok_eq!(from.transition_to(to), to);
+        }
+    }
+
+    #[test]
+    fn illegal_transitions_are_rejected() {
+        use SubscriptionStatus::*;
+
+        for (from, to) in [(Unsubscribed, Confirmed), (Confirmed, PendingConfirmation)] {
+            assert_err!(from.transition_to(to));
+        }
+    }
+}
@@ -0,0 +1,86 @@
+/// Role of a user, each role can do everything the roles below it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, sqlx::Type)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE", try_from = "String")]
+#[sqlx(type_name = "user_role", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UserRole {
+    Admin,
+    Editor,
+    Viewer,
+}
+
+impl UserRole {
+    pub fn parse(s: String) -> Result<UserRole, String> {
+        match s.to_uppercase().as_str() {
+            "ADMIN" => Ok(Self::Admin),
+            "EDITOR" => Ok(Self::Editor),
+            "VIEWER" => Ok(Self::Viewer),
+            _ => Err(format!("{} is not a valid user role", s)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserRole::Admin => "ADMIN",
+            UserRole::Editor => "EDITOR",
+            UserRole::Viewer => "VIEWER",
+        }
+    }
+
+    /// Whether this role has at least the rights of `other`.
+    pub fn includes(self, other: UserRole) -> bool {
+        self.rank() >= other.rank()
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            UserRole::Viewer => 0,
+            UserRole::Editor => 1,
+            UserRole::Admin => 2,
+        }
+    }
+}
+
+impl TryFrom<String> for UserRole {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::parse(s)
+    }
+}
+
+impl std::fmt::Display for UserRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::UserRole;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn known_roles_are_parsed_case_insensitively() {
+        for role in [UserRole::Admin, UserRole::Editor, UserRole::Viewer] {
+            assert_ok_eq!(UserRole::parse(role.as_str().to_lowercase()), role);
+        }
+    }
+
+    #[test]
+    fn unknown_role_is_rejected() {
+        assert_err!(UserRole::parse("OWNER".into()));
+    }
+
+    #[test]
+    fn roles_include_the_roles_below_them() {
+        use UserRole::*;
+
+        assert!(Admin.includes(Editor));
+        assert!(Admin.includes(Viewer));
+        assert!(Editor.includes(Viewer));
+        assert!(Viewer.includes(Viewer));
+
+        assert!(!Editor.includes(Admin));
+        assert!(!Viewer.includes(Editor));
+    }
+}
@@ -1,13 +1,19 @@
-use reqwest::Client;
+use reqwest::{header::HeaderMap, Client};
 use secrecy::{ExposeSecret, Secret};
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::SubscriberEmail,
+    metrics::Metrics,
+    request_id::{current_request_id, RequestId},
+    telemetry::inject_trace_context,
+};
 
 pub struct EmailClient {
     http_client: Client,
     base_url: String,
     sender: SubscriberEmail,
     authorization_token: Secret<String>,
+    metrics: Option<Metrics>,
 }
 
 impl EmailClient {
@@ -22,9 +28,16 @@ impl EmailClient {
             base_url: base_url.into(),
             sender,
             authorization_token,
+            metrics: None,
         }
     }
 
+    /// Counts sent and failed emails in `metrics`.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
     pub async fn send_email(
         &self,
         recipient: &SubscriberEmail,
@@ -39,19 +52,37 @@ impl EmailClient {
             subject,
             html_body: html_content,
             text_body: text_content,
+            metadata: current_request_id().map(|request_id| EmailMetadata { request_id }),
         };
 
-        let _ = self
+        // Lets the trace of a publish continue into the email API
+        let mut headers = HeaderMap::new();
+        inject_trace_context(&mut headers);
+
+        let outcome = self
             .http_client
             .post(url)
+            .headers(headers)
             .json(&request_body)
             .header(
                 "X-Postmark-Server-Token",
                 self.authorization_token.expose_secret(),
             )
             .send()
-            .await?
-            .error_for_status()?;
+            .await
+            .and_then(|response| response.error_for_status());
+
+        if let Some(metrics) = &self.metrics {
+            let label = if outcome.is_ok() { "sent" } else { "failed" };
+            metrics.emails_total.with_label_values(&[label]).inc();
+        }
+
+        outcome.map(|_| ())
+    }
+
+    /// Checks that the email API answers, whatever the response status.
+    pub async fn check_reachable(&self) -> Result<(), reqwest::Error> {
+        self.http_client.head(&self.base_url).send().await?;
 
         Ok(())
     }
@@ -65,6 +96,14 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<EmailMetadata>,
+}
+
+/// Postmark metadata, to find the request that sent an email.
+#[derive(serde::Serialize)]
+struct EmailMetadata {
+    request_id: RequestId,
 }
 
 #[cfg(test)]
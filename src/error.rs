@@ -0,0 +1,154 @@
+use actix_web::{
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    HttpResponse, ResponseError,
+};
+
+use crate::{
+    authentication::{AuthError, TOTP_CODE_HEADER},
+    problem::{FieldError, ProblemDetails},
+};
+
+/// Error returned by every request handler.
+///
+/// Client errors (4xx) describe what is wrong with the request and that
+/// description is sent back. Server errors (5xx) wrap the underlying cause,
+/// which is only logged: `TracingLogger` records the `Debug` representation,
+/// i.e. the full cause chain, once per failed request.
+#[derive(thiserror::Error)]
+pub enum AppError {
+    #[error("{0}")]
+    ValidationError(String),
+
+    #[error("{}", display_field_errors(.0))]
+    FieldValidationError(Vec<FieldError>),
+
+    #[error("Authentication failed")]
+    AuthError {
+        realm: &'static str,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    #[error("A two-factor code is required in the {} header", TOTP_CODE_HEADER)]
+    SecondFactorRequired { realm: &'static str },
+
+    #[error("{0}")]
+    Forbidden(String),
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    #[error("Too many requests, retry in {} seconds", .retry_after.as_secs())]
+    RateLimited { retry_after: std::time::Duration },
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl AppError {
+    pub fn auth_error(realm: &'static str, source: impl Into<anyhow::Error>) -> Self {
+        Self::AuthError {
+            realm,
+            source: source.into(),
+        }
+    }
+
+    /// Maps a credential validation failure to a 401 for `realm`, a 403 for
+    /// an API token lacking the scope or a 429 while locked out, unless it
+    /// was caused by something other than the credentials. A missing
+    /// two-factor code is a 401 telling the client where to send it.
+    pub fn from_auth_error(realm: &'static str, e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => Self::auth_error(realm, e),
+            AuthError::SecondFactorRequired => Self::SecondFactorRequired { realm },
+            AuthError::LockedOut { retry_after } => Self::RateLimited { retry_after },
+            AuthError::MissingScope(_) => Self::Forbidden(e.to_string()),
+            AuthError::UnexpectedError(_) => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl std::fmt::Debug for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::ValidationError(_) | AppError::FieldValidationError(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            AppError::AuthError { .. } | AppError::SecondFactorRequired { .. } => {
+                StatusCode::UNAUTHORIZED
+            }
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let status = self.status_code();
+        let mut problem = ProblemDetails::new(status);
+
+        if status.is_client_error() {
+            problem = problem.with_detail(self.to_string());
+        }
+
+        if let AppError::FieldValidationError(errors) = self {
+            problem = problem.with_errors(errors.clone());
+        }
+
+        let mut response = problem.into_response();
+
+        if let AppError::AuthError { realm, .. } | AppError::SecondFactorRequired { realm } = self {
+            let header_value = HeaderValue::from_str(&format!(r#"Basic realm="{}""#, realm))
+                .expect("Invalid realm");
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, header_value);
+        }
+
+        if let AppError::RateLimited { retry_after } = self {
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after.as_secs()),
+            );
+        }
+
+        response
+    }
+}
+
+fn display_field_errors(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.message.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused By:\n\t{}", cause)?;
+        current = cause.source();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use sqlx::{migrate::Migrator, PgPool};
+
+use crate::{configuration::HealthSettings, email_client::EmailClient};
+
+/// Health of one dependency of the application. Why a check failed is
+/// only logged, the readiness endpoint is public.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ComponentHealth {
+    pub status: ComponentStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentStatus {
+    Ok,
+    Failing,
+    /// Not checked, as configured.
+    Skipped,
+}
+
+/// Whether the application can serve traffic, with a breakdown by
+/// component.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+/// Runs the readiness checks, each bounded by a timeout, and caches their
+/// outcome so that frequent probes do not load Postgres or the email
+/// provider.
+#[derive(Clone)]
+pub struct HealthChecker {
+    migrator: &'static Migrator,
+    timeout: Duration,
+    cache_ttl: Duration,
+    check_email_provider: bool,
+    cached: Arc<Mutex<Option<(Instant, Readiness)>>>,
+}
+
+impl HealthChecker {
+    pub fn new(settings: &HealthSettings, migrator: &'static Migrator) -> Self {
+        Self {
+            migrator,
+            timeout: Duration::from_millis(settings.timeout_milliseconds),
+            cache_ttl: Duration::from_secs(settings.cache_seconds),
+            check_email_provider: settings.check_email_provider,
+            cached: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub async fn readiness(&self, db_pool: &PgPool, email_client: &EmailClient) -> Readiness {
+        if let Some((checked_at, readiness)) = self.cached.lock().unwrap().as_ref() {
+            if checked_at.elapsed() < self.cache_ttl {
+                return readiness.clone();
+            }
+        }
+
+        let (database, migrations, email_provider) = tokio::join!(
+            self.check("database", check_database(db_pool)),
+            self.check("migrations", check_migrations(self.migrator, db_pool)),
+            async {
+                if self.check_email_provider {
+                    self.check("email_provider", check_email_provider(email_client))
+                        .await
+                } else {
+                    ComponentHealth {
+                        status: ComponentStatus::Skipped,
+                    }
+                }
+            }
+        );
+
+        let components = BTreeMap::from([
+            ("database", database),
+            ("migrations", migrations),
+            ("email_provider", email_provider),
+        ]);
+        let readiness = Readiness {
+            ready: components
+                .values()
+                .all(|c| c.status != ComponentStatus::Failing),
+            components,
+        };
+
+        *self.cached.lock().unwrap() = Some((Instant::now(), readiness.clone()));
+
+        readiness
+    }
+
+    async fn check(
+        &self,
+        component: &'static str,
+        check: impl Future<Output = Result<(), anyhow::Error>>,
+    ) -> ComponentHealth {
+        let started_at = Instant::now();
+
+        let outcome = match tokio::time::timeout(self.timeout, check).await {
+            Ok(outcome) => outcome,
+            Err(_) => Err(anyhow::anyhow!(
+                "Timed out after {}ms",
+                self.timeout.as_millis()
+            )),
+        };
+
+        let status = match outcome {
+            Ok(()) => ComponentStatus::Ok,
+            Err(e) => {
+                tracing::error!(
+                    component,
+                    duration_ms = started_at.elapsed().as_millis() as u64,
+                    error.cause_chain = ?e,
+                    "Readiness check failed"
+                );
+                ComponentStatus::Failing
+            }
+        };
+
+        ComponentHealth { status }
+    }
+}
+
+#[tracing::instrument(name = "Check database", skip(db_pool))]
+async fn check_database(db_pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query("SELECT 1")
+        .execute(db_pool)
+        .await
+        .context("Database round-trip failed")?;
+
+    Ok(())
+}
+
+/// Fails when a migration embedded in this build has not been applied.
+#[tracing::instrument(name = "Check migrations", skip(migrator, db_pool))]
+async fn check_migrations(migrator: &Migrator, db_pool: &PgPool) -> Result<(), anyhow::Error> {
+    let applied = applied_migrations(db_pool).await?;
+
+    let pending: Vec<String> = migrator
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration() && !applied.contains(&m.version))
+        .map(|m| m.version.to_string())
+        .collect();
+
+    if !pending.is_empty() {
+        anyhow::bail!("Pending migrations: {}", pending.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Versions of the successfully applied migrations, none when the
+/// migrations table does not exist yet.
+pub async fn applied_migrations(db_pool: &PgPool) -> Result<Vec<i64>, anyhow::Error> {
+    let has_migrations_table: bool =
+        sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(db_pool)
+            .await
+            .context("Failed to look for the migrations table")?;
+
+    if !has_migrations_table {
+        return Ok(vec![]);
+    }
+
+    sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+        .fetch_all(db_pool)
+        .await
+        .context("Failed to fetch applied migrations")
+}
+
+#[tracing::instrument(name = "Check email provider", skip(email_client))]
+async fn check_email_provider(email_client: &EmailClient) -> Result<(), anyhow::Error> {
+    email_client
+        .check_reachable()
+        .await
+        .context("Email provider is unreachable")
+}
@@ -1,6 +1,20 @@
+pub mod audit;
+pub mod authentication;
+pub mod cli;
+pub mod client_ip;
 pub mod configuration;
+pub mod deliverability;
 pub mod domain;
 pub mod email_client;
+pub mod error;
+pub mod health;
+pub mod json_or_form;
+pub mod metrics;
+pub mod problem;
+pub mod rate_limit;
+pub mod request_id;
 pub mod routes;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
+pub mod users;
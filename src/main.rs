@@ -1,21 +1,82 @@
+use clap::Parser;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use zero2prod_rust::{
+    authentication::{LoginThrottle, PasswordHashing},
+    cli::{check_configuration, run_admin_command, AdminContext, Cli, Command, ConfigCommand},
     configuration,
-    startup::{get_connection_pool, Application},
-    telemetry::{get_subscriber, init_subscriber},
+    email_client::EmailClient,
+    startup::{get_connection_pool, run_migrations, Application},
+    telemetry::{get_subscriber, init_subscriber, init_tracer_provider, tracer},
 };
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    // Setup telemetry
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+async fn main() -> Result<(), anyhow::Error> {
+    let command = Cli::parse().command.unwrap_or(Command::Serve);
+    if let Command::Config {
+        command: ConfigCommand::Check,
+    } = command
+    {
+        let config = configuration::load_configuration()?;
+        check_configuration(&config, &mut std::io::stdout())?;
+        eprintln!("Configuration is valid");
+        return Ok(());
+    }
+
+    let config = configuration::get_configuration()?;
+
+    // Setup telemetry, keeping stdout for the output of admin commands
+    let log_sink = match command {
+        Command::Admin(_) => BoxMakeWriter::new(std::io::stderr),
+        _ => BoxMakeWriter::new(std::io::stdout),
+    };
+    let tracer_provider =
+        init_tracer_provider(&config.tracing).expect("Failed to set up trace export");
+    let subscriber = get_subscriber(
+        "zero2prod".into(),
+        &config.logging,
+        log_sink,
+        tracer_provider.as_ref().map(tracer),
+    )
+    .expect("Failed to set up logging");
     init_subscriber(subscriber);
 
-    let config = configuration::get_configuration().expect("Failed to load config");
     let db_pool = get_connection_pool(&config.database);
 
-    Application::build(config, db_pool)
-        .await
-        .expect("Failed to build application")
-        .run_until_stopped()
-        .await
+    let outcome = match command {
+        Command::Serve => Application::build(config, db_pool.clone())
+            .await
+            .expect("Failed to build application")
+            .run_until_stopped()
+            .await
+            .map_err(anyhow::Error::from),
+        Command::Migrate => run_migrations(&db_pool).await,
+        Command::Config { .. } => unreachable!("Handled before loading the configuration"),
+        Command::Admin(command) => {
+            let email_client = EmailClient::new(
+                config.email.base_url.as_str(),
+                config.email.sender(),
+                config.email.authorization_token.clone(),
+                config.email.timeout(),
+            );
+            let password_hashing = PasswordHashing::new(&config.password_hashing)?;
+            let login_throttle = LoginThrottle::new(&config.login_throttle);
+            let context = AdminContext {
+                db_pool: &db_pool,
+                email_client: &email_client,
+                password_hashing: &password_hashing,
+                login_throttle: &login_throttle,
+            };
+
+            run_admin_command(command, context, &mut std::io::stdout()).await
+        }
+    };
+    db_pool.close().await;
+
+    if let Some(tracer_provider) = tracer_provider {
+        if let Err(e) = tracer_provider.shutdown() {
+            eprintln!("Failed to flush spans: {e}");
+        }
+    }
+
+    outcome
 }
@@ -0,0 +1,176 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web,
+};
+use anyhow::Context;
+use prometheus::{
+    Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use sqlx::PgPool;
+
+/// Route label of requests matching no route, so that scanners probing
+/// random paths cannot blow up the number of series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Prometheus metrics of the application, kept in their own registry so
+/// that every `Application` reports only its own numbers.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests_total: IntCounterVec,
+    http_request_duration_seconds: HistogramVec,
+    pub subscriptions_created_total: IntCounter,
+    pub subscriptions_confirmed_total: IntCounter,
+    pub emails_total: IntCounterVec,
+    pub newsletter_publish_duration_seconds: Histogram,
+    db_pool_connections: IntGaugeVec,
+    db_pool_max_connections: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("zero2prod".into()), None)?;
+
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &["method", "route"],
+        )?;
+        let subscriptions_created_total = IntCounter::new(
+            "subscriptions_created_total",
+            "Subscriptions waiting for confirmation",
+        )?;
+        let subscriptions_confirmed_total = IntCounter::new(
+            "subscriptions_confirmed_total",
+            "Subscriptions confirmed with their token",
+        )?;
+        let emails_total = IntCounterVec::new(
+            Opts::new("emails_total", "Emails handed to the email API"),
+            &["outcome"],
+        )?;
+        let newsletter_publish_duration_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "newsletter_publish_duration_seconds",
+                "Time taken to send a newsletter to every confirmed subscriber",
+            )
+            .buckets(vec![0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0, 900.0]),
+        )?;
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database connections by state"),
+            &["state"],
+        )?;
+        let db_pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Maximum number of database connections",
+        )?;
+
+        registry.register(Box::new(http_requests_total.clone()))?;
+        registry.register(Box::new(http_request_duration_seconds.clone()))?;
+        registry.register(Box::new(subscriptions_created_total.clone()))?;
+        registry.register(Box::new(subscriptions_confirmed_total.clone()))?;
+        registry.register(Box::new(emails_total.clone()))?;
+        registry.register(Box::new(newsletter_publish_duration_seconds.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(db_pool_max_connections.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            subscriptions_created_total,
+            subscriptions_confirmed_total,
+            emails_total,
+            newsletter_publish_duration_seconds,
+            db_pool_connections,
+            db_pool_max_connections,
+        })
+    }
+
+    /// Renders every metric in the Prometheus text format, sampling the
+    /// connection pool at the time of the scrape.
+    pub fn render(&self, db_pool: &PgPool) -> Result<String, anyhow::Error> {
+        let size = i64::from(db_pool.size());
+        let idle = i64::try_from(db_pool.num_idle()).unwrap_or(i64::MAX);
+
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["active"])
+            .set(size - idle);
+        self.db_pool_max_connections
+            .set(i64::from(db_pool.options().get_max_connections()));
+
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .context("Failed to encode metrics")
+    }
+}
+
+/// Middleware counting requests and their latency per route pattern.
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+    let started_at = Instant::now();
+
+    let response = next.call(req).await;
+
+    if let Some(metrics) = metrics {
+        let status = match &response {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+
+        metrics
+            .http_requests_total
+            .with_label_values(&[&method, &route, status.as_str()])
+            .inc();
+        metrics
+            .http_request_duration_seconds
+            .with_label_values(&[&method, &route])
+            .observe(started_at.elapsed().as_secs_f64());
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::Metrics;
+
+    #[tokio::test]
+    async fn metrics_are_rendered_in_text_format() {
+        let metrics = Metrics::new().unwrap();
+        let db_pool = PgPoolOptions::new()
+            .max_connections(7)
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+
+        metrics.subscriptions_created_total.inc();
+        metrics.emails_total.with_label_values(&["failed"]).inc();
+        let rendered = metrics.render(&db_pool).unwrap();
+
+        assert!(rendered.contains("zero2prod_subscriptions_created_total 1"));
+        assert!(rendered.contains(r#"zero2prod_emails_total{outcome="failed"} 1"#));
+        assert!(rendered.contains("zero2prod_db_pool_max_connections 7"));
+    }
+}
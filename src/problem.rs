@@ -0,0 +1,136 @@
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::{header::HeaderValue, StatusCode},
+    middleware::Next,
+    HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+
+use crate::request_id::RequestId;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// An RFC 7807 `application/problem+json` error body.
+///
+/// Only client-safe information goes in here: the cause chain of server
+/// errors stays in the logs.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+}
+
+/// A validation failure tied to the request field that caused it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            problem_type: "about:blank",
+            title: status
+                .canonical_reason()
+                .unwrap_or("Unknown error")
+                .to_string(),
+            status: status.as_u16(),
+            detail: None,
+            request_id: None,
+            errors: vec![],
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// Builds the error response. The problem is also stored in the
+    /// response extensions so `add_request_id` can complete it.
+    pub fn into_response(self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = HttpResponse::build(status)
+            .content_type(PROBLEM_JSON)
+            .body(self.to_body());
+        response.extensions_mut().insert(self);
+        response
+    }
+
+    fn to_body(&self) -> String {
+        serde_json::to_string(self).expect("Failed to serialize problem details")
+    }
+}
+
+/// Middleware filling in the request id of problem responses, which is not
+/// known where `ResponseError::error_response` builds them. Errors of inner
+/// middleware are turned into their response here so they get it too.
+pub async fn add_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let request_id = req.extensions().get::<RequestId>().cloned();
+
+    match next.call(req).await {
+        Ok(res) => {
+            let (req, res) = res.map_into_boxed_body().into_parts();
+            Ok(ServiceResponse::new(
+                req,
+                with_request_id(res, request_id.as_ref()),
+            ))
+        }
+        Err(err) => {
+            let res = with_request_id(err.error_response(), request_id.as_ref());
+            Err(InternalError::from_response(err, res).into())
+        }
+    }
+}
+
+fn with_request_id(res: HttpResponse, request_id: Option<&RequestId>) -> HttpResponse {
+    let problem = res.extensions().get::<ProblemDetails>().cloned();
+
+    match (problem, request_id) {
+        (Some(mut problem), Some(request_id)) => {
+            problem.request_id = Some(request_id.to_string());
+            let mut res = res.set_body(BoxBody::new(problem.to_body()));
+            res.headers_mut().insert(
+                actix_web::http::header::CONTENT_TYPE,
+                HeaderValue::from_static(PROBLEM_JSON),
+            );
+            res
+        }
+        _ => res,
+    }
+}
+
+/// Error handler for the built-in extractors (`Json`, `Form`, `Query`,
+/// `Path`) so malformed requests are answered with problem details too.
+pub fn extractor_error_handler<E>(err: E, _req: &HttpRequest) -> actix_web::Error
+where
+    E: ResponseError + 'static,
+{
+    let response = ProblemDetails::new(err.status_code())
+        .with_detail(err.to_string())
+        .into_response();
+    InternalError::from_response(err, response).into()
+}
+
+/// Fallback for requests that do not match any route.
+pub async fn not_found() -> HttpResponse {
+    ProblemDetails::new(StatusCode::NOT_FOUND).into_response()
+}
@@ -0,0 +1,411 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web,
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    client_ip::ClientIp,
+    configuration::{BucketSettings, RateLimitSettings, RateLimitStoreKind},
+    error::AppError,
+};
+
+/// Above this many tracked keys the in-memory store forgets the buckets
+/// that have refilled completely, they are equivalent to missing ones.
+const IN_MEMORY_PRUNE_THRESHOLD: usize = 10_000;
+
+/// How often an instance deletes the Postgres buckets that have refilled
+/// completely.
+const POSTGRES_PRUNE_INTERVAL: TimeDelta = TimeDelta::minutes(1);
+
+/// A bucket of `capacity` tokens, refilled with one token every
+/// `refill_every`. Each request takes a token.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBucket {
+    capacity: u32,
+    refill_every: Duration,
+}
+
+/// Stored state of a single bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketState {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Admission {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, refill_every: Duration) -> Self {
+        Self {
+            capacity,
+            refill_every,
+        }
+    }
+
+    pub fn full(&self, now: DateTime<Utc>) -> BucketState {
+        BucketState {
+            tokens: self.capacity as f64,
+            updated_at: now,
+        }
+    }
+
+    /// Refills `state` up to `now` and tries to take a token from it.
+    pub fn take(&self, state: BucketState, now: DateTime<Utc>) -> (BucketState, Admission) {
+        let tokens = self.tokens_at(&state, now);
+
+        if tokens >= 1.0 {
+            let state = BucketState {
+                tokens: tokens - 1.0,
+                updated_at: now,
+            };
+            return (state, Admission::Allowed);
+        }
+
+        let missing = self.refill_every.mul_f64(1.0 - tokens);
+        let state = BucketState {
+            tokens,
+            updated_at: now,
+        };
+        // Whole seconds, as sent in `Retry-After`
+        let retry_after = Duration::from_secs(missing.as_millis().div_ceil(1000) as u64);
+        (state, Admission::Limited { retry_after })
+    }
+
+    fn tokens_at(&self, state: &BucketState, now: DateTime<Utc>) -> f64 {
+        let elapsed = (now - state.updated_at).to_std().unwrap_or_default();
+        let refilled = elapsed.as_secs_f64() / self.refill_every.as_secs_f64();
+        (state.tokens + refilled).min(self.capacity as f64)
+    }
+
+    /// Time an empty bucket takes to refill completely.
+    pub fn refill_time(&self) -> Duration {
+        self.refill_every * self.capacity
+    }
+
+    fn is_full_at(&self, state: &BucketState, now: DateTime<Utc>) -> bool {
+        self.tokens_at(state, now) >= self.capacity as f64
+    }
+}
+
+impl From<&BucketSettings> for TokenBucket {
+    fn from(settings: &BucketSettings) -> Self {
+        Self::new(settings.capacity, settings.refill_every())
+    }
+}
+
+/// Where bucket states live between requests.
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    async fn take(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+        now: DateTime<Utc>,
+    ) -> Result<Admission, anyhow::Error>;
+}
+
+/// Buckets kept in process memory, only suitable for a single instance.
+#[derive(Default)]
+pub struct InMemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, BucketState>>,
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn take(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+        now: DateTime<Utc>,
+    ) -> Result<Admission, anyhow::Error> {
+        let mut buckets = self.buckets.lock().expect("Rate limit store lock poisoned");
+
+        if buckets.len() >= IN_MEMORY_PRUNE_THRESHOLD {
+            buckets.retain(|_, state| !bucket.is_full_at(state, now));
+        }
+
+        let state = buckets.get(key).copied().unwrap_or(bucket.full(now));
+        let (state, admission) = bucket.take(state, now);
+        buckets.insert(key.to_string(), state);
+
+        Ok(admission)
+    }
+}
+
+/// Buckets stored in Postgres and shared by every instance.
+///
+/// Buckets left untouched for `stale_after` have refilled completely and
+/// are deleted now and then, so the table does not grow with every client
+/// and address ever seen.
+pub struct PostgresRateLimitStore {
+    db_pool: PgPool,
+    stale_after: Duration,
+    last_pruned: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl PostgresRateLimitStore {
+    /// `stale_after` must be at least the `refill_time` of every bucket
+    /// taken from the store.
+    pub fn new(db_pool: PgPool, stale_after: Duration) -> Self {
+        Self {
+            db_pool,
+            stale_after,
+            last_pruned: Mutex::new(None),
+        }
+    }
+
+    fn is_prune_due(&self, now: DateTime<Utc>) -> bool {
+        let mut last_pruned = self.last_pruned.lock().expect("Prune time lock poisoned");
+
+        match *last_pruned {
+            Some(at) if now - at < POSTGRES_PRUNE_INTERVAL => false,
+            _ => {
+                *last_pruned = Some(now);
+                true
+            }
+        }
+    }
+
+    #[tracing::instrument(name = "Prune rate limit buckets", skip(self))]
+    async fn prune(&self, now: DateTime<Utc>) -> Result<u64, anyhow::Error> {
+        let stale_before = now - TimeDelta::from_std(self.stale_after)?;
+
+        let pruned = sqlx::query!(
+            r#"DELETE FROM rate_limit_buckets WHERE updated_at < $1"#,
+            stale_before
+        )
+        .execute(&self.db_pool)
+        .await?
+        .rows_affected();
+
+        Ok(pruned)
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for PostgresRateLimitStore {
+    #[tracing::instrument(name = "Take rate limit token", skip(self, bucket))]
+    async fn take(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+        now: DateTime<Utc>,
+    ) -> Result<Admission, anyhow::Error> {
+        let mut transaction = self.db_pool.begin().await?;
+        let full = bucket.full(now);
+
+        sqlx::query!(
+            r#"INSERT INTO rate_limit_buckets (key, tokens, updated_at) VALUES ($1, $2, $3)
+            ON CONFLICT (key) DO NOTHING"#,
+            key,
+            full.tokens,
+            full.updated_at
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        let state = sqlx::query_as!(
+            BucketState,
+            r#"SELECT tokens, updated_at FROM rate_limit_buckets WHERE key = $1 FOR UPDATE"#,
+            key
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        let (state, admission) = bucket.take(state, now);
+
+        sqlx::query!(
+            r#"UPDATE rate_limit_buckets SET tokens = $2, updated_at = $3 WHERE key = $1"#,
+            key,
+            state.tokens,
+            state.updated_at
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        if self.is_prune_due(now) {
+            if let Err(e) = self.prune(now).await {
+                tracing::warn!(error.cause_chain = ?e, "Failed to prune rate limit buckets");
+            }
+        }
+
+        Ok(admission)
+    }
+}
+
+/// Limits requests to the public subscription and password reset endpoints
+/// per client IP and per target email address.
+pub struct RateLimiter {
+    store: Option<Box<dyn RateLimitStore>>,
+    per_ip: TokenBucket,
+    per_email: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn from_settings(settings: &RateLimitSettings, db_pool: PgPool) -> Self {
+        let per_ip: TokenBucket = (&settings.per_ip).into();
+        let per_email: TokenBucket = (&settings.per_email).into();
+        let stale_after = per_ip.refill_time().max(per_email.refill_time());
+
+        let store: Option<Box<dyn RateLimitStore>> = match (settings.enabled, &settings.store) {
+            (false, _) => None,
+            (true, RateLimitStoreKind::Memory) => Some(Box::<InMemoryRateLimitStore>::default()),
+            (true, RateLimitStoreKind::Postgres) => {
+                Some(Box::new(PostgresRateLimitStore::new(db_pool, stale_after)))
+            }
+        };
+
+        Self {
+            store,
+            per_ip,
+            per_email,
+        }
+    }
+
+    pub async fn check_ip(&self, ip: &str) -> Result<(), AppError> {
+        self.check(&format!("ip:{}", ip), &self.per_ip).await
+    }
+
+    pub async fn check_subscription_email(&self, email: &str) -> Result<(), AppError> {
+        self.check(&format!("email:{}", email.to_lowercase()), &self.per_email)
+            .await
+    }
+
+    /// Password reset requests have their own buckets, so that signing an
+    /// address up does not use up its reset allowance, or the reverse.
+    pub async fn check_reset_email(&self, email: &str) -> Result<(), AppError> {
+        self.check(
+            &format!("reset-email:{}", email.to_lowercase()),
+            &self.per_email,
+        )
+        .await
+    }
+
+    async fn check(&self, key: &str, bucket: &TokenBucket) -> Result<(), AppError> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+
+        match store.take(key, bucket, Utc::now()).await {
+            Ok(Admission::Allowed) => Ok(()),
+            Ok(Admission::Limited { retry_after }) => {
+                tracing::info!(
+                    key,
+                    retry_after = retry_after.as_secs(),
+                    "Request rate limited"
+                );
+                Err(AppError::RateLimited { retry_after })
+            }
+            // An unavailable store should not take the endpoints down
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, "Failed to check rate limit, allowing request");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Middleware rejecting requests from a client IP that ran out of tokens.
+pub async fn limit_by_ip(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    if let Some(rate_limiter) = req.app_data::<web::Data<RateLimiter>>() {
+        if let ClientIp(Some(ip)) = ClientIp::of(req.request()) {
+            if let Err(e) = rate_limiter.check_ip(&ip).await {
+                return Ok(req.error_response(e));
+            }
+        }
+    }
+
+    Ok(next.call(req).await?.map_into_boxed_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeDelta, Utc};
+    use claims::{assert_matches, assert_ok_eq};
+
+    use super::{Admission, InMemoryRateLimitStore, RateLimitStore, TokenBucket};
+
+    fn bucket() -> TokenBucket {
+        TokenBucket::new(2, Duration::from_secs(10))
+    }
+
+    #[test]
+    fn full_bucket_allows_up_to_capacity_requests() {
+        let bucket = bucket();
+        let now = Utc::now();
+        let mut state = bucket.full(now);
+
+        for _ in 0..2 {
+            let (next, admission) = bucket.take(state, now);
+            assert_eq!(admission, Admission::Allowed);
+            state = next;
+        }
+
+        let (_, admission) = bucket.take(state, now);
+        assert_eq!(
+            admission,
+            Admission::Limited {
+                retry_after: Duration::from_secs(10)
+            }
+        );
+    }
+
+    #[test]
+    fn tokens_are_refilled_over_time() {
+        let bucket = bucket();
+        let now = Utc::now();
+        let empty = bucket.take(bucket.take(bucket.full(now), now).0, now).0;
+
+        let (_, admission) = bucket.take(empty, now + TimeDelta::seconds(4));
+        assert_eq!(
+            admission,
+            Admission::Limited {
+                retry_after: Duration::from_secs(6)
+            }
+        );
+
+        let (_, admission) = bucket.take(empty, now + TimeDelta::seconds(10));
+        assert_eq!(admission, Admission::Allowed);
+    }
+
+    #[test]
+    fn refill_does_not_exceed_capacity() {
+        let bucket = bucket();
+        let now = Utc::now();
+
+        let (state, _) = bucket.take(bucket.full(now), now + TimeDelta::hours(1));
+
+        assert_eq!(state.tokens, 1.0);
+    }
+
+    #[tokio::test]
+    async fn in_memory_store_keeps_buckets_per_key() {
+        let store = InMemoryRateLimitStore::default();
+        let bucket = TokenBucket::new(1, Duration::from_secs(60));
+        let now = Utc::now();
+
+        assert_ok_eq!(store.take("a", &bucket, now).await, Admission::Allowed);
+        assert_matches!(
+            store.take("a", &bucket, now).await,
+            Ok(Admission::Limited { .. })
+        );
+        assert_ok_eq!(store.take("b", &bucket, now).await, Admission::Allowed);
+    }
+}
@@ -0,0 +1,194 @@
+use std::future::Future;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    HttpMessage,
+};
+use uuid::Uuid;
+
+/// Header carrying the request id, in both directions.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: RequestId;
+}
+
+/// Identifier of a request, found in its logs, its error responses and the
+/// emails it sends.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(transparent)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// Accepts a caller-supplied id if it is short and made of characters
+    /// safe to log and echo back.
+    pub fn parse(s: &str) -> Option<Self> {
+        let is_valid = !s.is_empty()
+            && s.len() <= MAX_REQUEST_ID_LENGTH
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+        is_valid.then(|| Self(s.to_string()))
+    }
+}
+
+impl AsRef<str> for RequestId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The id of the request being handled by the current task, if any.
+pub fn current_request_id() -> Option<RequestId> {
+    CURRENT_REQUEST_ID.try_with(RequestId::clone).ok()
+}
+
+/// Carries the request id of the current task into `future`, for work
+/// spawned off a request.
+pub fn with_current_request_id<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let request_id = current_request_id();
+
+    async move {
+        match request_id {
+            Some(request_id) => CURRENT_REQUEST_ID.scope(request_id, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// Middleware assigning the request id, reusing the caller's `X-Request-Id`
+/// when valid, and returning it in the response headers, those built from
+/// errors included.
+///
+/// Must wrap `TracingLogger` so that the root span is created with it.
+pub async fn propagate_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(RequestId::parse)
+        .unwrap_or_else(RequestId::generate);
+    req.extensions_mut().insert(request_id.clone());
+
+    let header_name = HeaderName::from_static("x-request-id");
+    let header_value =
+        HeaderValue::from_str(request_id.as_ref()).expect("Request ids are valid header values");
+
+    match CURRENT_REQUEST_ID.scope(request_id, next.call(req)).await {
+        Ok(mut res) => {
+            res.headers_mut().insert(header_name, header_value);
+            Ok(res)
+        }
+        // The response is only built from the error further out, build it
+        // here instead to add the header
+        Err(err) => {
+            let mut res = err.error_response();
+            res.headers_mut().insert(header_name, header_value);
+            Err(InternalError::from_response(err, res).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        body::{to_bytes, BoxBody, MessageBody},
+        dev::{Service, ServiceRequest, ServiceResponse},
+        middleware::{from_fn, Next},
+        test::{init_service, TestRequest},
+        App,
+    };
+    use claims::{assert_none, assert_some};
+
+    use super::RequestId;
+    use crate::{error::AppError, problem};
+
+    #[test]
+    fn caller_supplied_ids_are_accepted() {
+        for id in ["a3f1c2", "req_42.retry-1", &"a".repeat(128)] {
+            assert_some!(RequestId::parse(id));
+        }
+    }
+
+    #[test]
+    fn unsafe_or_long_ids_are_rejected() {
+        for id in ["", "two words", "line\nbreak", "<script>", &"a".repeat(129)] {
+            assert_none!(RequestId::parse(id));
+        }
+    }
+
+    #[tokio::test]
+    async fn request_id_is_carried_into_spawned_work() {
+        let request_id = RequestId::generate();
+
+        let carried = super::CURRENT_REQUEST_ID
+            .scope(request_id.clone(), async {
+                tokio::spawn(super::with_current_request_id(async {
+                    super::current_request_id()
+                }))
+                .await
+                .unwrap()
+            })
+            .await;
+
+        assert_eq!(carried, Some(request_id));
+    }
+
+    /// Stands for a middleware failing, such as a rate limiter whose store
+    /// is down.
+    async fn failing_middleware(
+        _: ServiceRequest,
+        _: Next<impl MessageBody>,
+    ) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+        Err(AppError::UnexpectedError(anyhow::anyhow!("Store unavailable")).into())
+    }
+
+    #[actix_web::test]
+    async fn errors_of_inner_middleware_carry_the_request_id() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(failing_middleware))
+                .wrap(from_fn(problem::add_request_id))
+                .wrap(from_fn(super::propagate_request_id)),
+        )
+        .await;
+        let request = TestRequest::get()
+            .insert_header((super::REQUEST_ID_HEADER, "support-ticket-42"))
+            .to_request();
+
+        let error = app
+            .call(request)
+            .await
+            .err()
+            .expect("The failure reaches the server as an error");
+        let response = error.error_response();
+
+        assert_eq!(response.status().as_u16(), 500);
+        assert_eq!(
+            response.headers().get(super::REQUEST_ID_HEADER).unwrap(),
+            "support-ticket-42"
+        );
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["request_id"], "support-ticket-42");
+    }
+}
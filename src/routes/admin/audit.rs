@@ -0,0 +1,150 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::AuditAction,
+    authentication::{Authorized, ViewAuditLog},
+    error::AppError,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(serde::Deserialize)]
+pub struct ListAuditLogQuery {
+    action: Option<AuditAction>,
+    actor_user_id: Option<Uuid>,
+    target_id: Option<String>,
+    occurred_after: Option<DateTime<Utc>>,
+    occurred_before: Option<DateTime<Utc>>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditLogEntry {
+    id: i64,
+    occurred_at: DateTime<Utc>,
+    action: String,
+    actor_user_id: Option<Uuid>,
+    target_id: Option<String>,
+    request_id: Option<String>,
+    details: serde_json::Value,
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditLogPage {
+    entries: Vec<AuditLogEntry>,
+    next_cursor: Option<String>,
+}
+
+/// Entries are listed newest first, so the cursor is the id of the last
+/// entry of a page, handed to clients as an opaque string.
+fn encode_cursor(id: i64) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(id.to_string())
+}
+
+fn decode_cursor(s: &str) -> Result<i64, String> {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(s)
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|decoded| decoded.parse().ok())
+        .ok_or_else(|| format!("{} is not a valid cursor", s))
+}
+
+#[tracing::instrument(name = "List audit log", skip(_user, query, db_pool))]
+pub async fn list_audit_log(
+    _user: Authorized<ViewAuditLog>,
+    query: web::Query<ListAuditLogQuery>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(decode_cursor)
+        .transpose()
+        .map_err(AppError::ValidationError)?;
+
+    let mut entries = fetch_audit_log(&query, cursor, limit + 1, &db_pool)
+        .await
+        .context("Failed to fetch audit log")?;
+
+    let next_cursor = if entries.len() as i64 > limit {
+        entries.truncate(limit as usize);
+        entries.last().map(|e| encode_cursor(e.id))
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(AuditLogPage {
+        entries,
+        next_cursor,
+    }))
+}
+
+#[tracing::instrument(name = "Fetch audit log page", skip(query, db_pool))]
+async fn fetch_audit_log(
+    query: &ListAuditLogQuery,
+    cursor: Option<i64>,
+    limit: i64,
+    db_pool: &PgPool,
+) -> Result<Vec<AuditLogEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        AuditLogEntry,
+        r#"
+    SELECT id, occurred_at, action, actor_user_id, target_id, request_id, details
+    FROM audit_log
+    WHERE ($1::text IS NULL OR action = $1)
+      AND ($2::uuid IS NULL OR actor_user_id = $2)
+      AND ($3::text IS NULL OR target_id = $3)
+      AND ($4::timestamptz IS NULL OR occurred_at >= $4)
+      AND ($5::timestamptz IS NULL OR occurred_at < $5)
+      AND ($6::bigint IS NULL OR id < $6)
+    ORDER BY id DESC
+    LIMIT $7
+    "#,
+        query.action.map(|a| a.as_str()),
+        query.actor_user_id,
+        query.target_id,
+        query.occurred_after,
+        query.occurred_before,
+        cursor,
+        limit,
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use claims::assert_err;
+
+    use super::{decode_cursor, encode_cursor};
+
+    #[test]
+    fn cursor_roundtrips() {
+        assert_eq!(decode_cursor(&encode_cursor(42)), Ok(42));
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        assert_err!(decode_cursor("not a cursor"));
+        assert_err!(decode_cursor(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD.encode("not a number")
+        ));
+    }
+}
@@ -0,0 +1,9 @@
+mod audit;
+mod stats;
+mod subscribers;
+mod users;
+
+pub use audit::*;
+pub use stats::*;
+pub use subscribers::*;
+pub use users::*;
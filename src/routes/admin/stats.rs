@@ -0,0 +1,56 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{Authorized, ViewStats},
+    domain::SubscriptionStatus,
+    error::AppError,
+};
+
+#[derive(serde::Serialize, Default)]
+pub struct SubscriberStats {
+    total: i64,
+    pending_confirmation: i64,
+    confirmed: i64,
+    unsubscribed: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct Stats {
+    subscribers: SubscriberStats,
+}
+
+#[tracing::instrument(name = "Get stats", skip(_user, db_pool))]
+pub async fn get_stats(
+    _user: Authorized<ViewStats>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let subscribers = count_subscribers(&db_pool)
+        .await
+        .context("Failed to count subscribers")?;
+
+    Ok(HttpResponse::Ok().json(Stats { subscribers }))
+}
+
+#[tracing::instrument(name = "Count subscribers by status", skip(db_pool))]
+async fn count_subscribers(db_pool: &PgPool) -> Result<SubscriberStats, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT status AS "status: SubscriptionStatus", COUNT(*) AS "count!"
+        FROM subscriptions GROUP BY status"#
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    let mut stats = SubscriberStats::default();
+    for row in rows {
+        stats.total += row.count;
+        match row.status {
+            SubscriptionStatus::PendingConfirmation => stats.pending_confirmation = row.count,
+            SubscriptionStatus::Confirmed => stats.confirmed = row.count,
+            SubscriptionStatus::Unsubscribed => stats.unsubscribed = row.count,
+        }
+    }
+
+    Ok(stats)
+}
@@ -0,0 +1,319 @@
+use std::ops::DerefMut;
+
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{Authorized, ManageSubscribers},
+    domain::SubscriptionStatus,
+    error::AppError,
+    routes::change_subscription_status,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(serde::Deserialize)]
+pub struct ListSubscribersQuery {
+    status: Option<SubscriptionStatus>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    email: Option<String>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateStatusBody {
+    status: SubscriptionStatus,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberRecord {
+    id: Uuid,
+    email: String,
+    display_email: String,
+    name: String,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberPage {
+    subscribers: Vec<SubscriberRecord>,
+    next_cursor: Option<String>,
+}
+
+/// Position of the last subscriber of a page in the `(subscribed_at, id)`
+/// ordering, handed to clients as an opaque string.
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let raw = format!(
+            "{}|{}",
+            self.subscribed_at
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.id
+        );
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    fn decode(s: &str) -> Result<Cursor, String> {
+        let invalid = || format!("{} is not a valid cursor", s);
+
+        let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (subscribed_at, id) = decoded.split_once('|').ok_or_else(invalid)?;
+
+        Ok(Cursor {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at)
+                .map_err(|_| invalid())?
+                .with_timezone(&Utc),
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[tracing::instrument(name = "List subscribers", skip(_user, query, db_pool))]
+pub async fn list_subscribers(
+    _user: Authorized<ManageSubscribers>,
+    query: web::Query<ListSubscribersQuery>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::ValidationError(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .map_err(AppError::ValidationError)?;
+
+    let mut subscribers = fetch_subscribers(&query, cursor.as_ref(), limit + 1, &db_pool)
+        .await
+        .context("Failed to fetch subscribers")?;
+
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|s| {
+            Cursor {
+                subscribed_at: s.subscribed_at,
+                id: s.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(SubscriberPage {
+        subscribers,
+        next_cursor,
+    }))
+}
+
+#[tracing::instrument(name = "Get subscriber", skip(_user, db_pool))]
+pub async fn get_subscriber_by_id(
+    _user: Authorized<ManageSubscribers>,
+    subscriber_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber = fetch_subscriber(*subscriber_id, db_pool.as_ref())
+        .await
+        .context("Failed to fetch subscriber")?
+        .ok_or_else(|| AppError::NotFound("Subscriber not found".into()))?;
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[tracing::instrument(name = "Manually confirm subscriber", skip(user, db_pool))]
+pub async fn confirm_subscriber(
+    user: Authorized<ManageSubscribers>,
+    subscriber_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber = set_subscriber_status(
+        user.user_id,
+        *subscriber_id,
+        SubscriptionStatus::Confirmed,
+        &db_pool,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[tracing::instrument(name = "Update subscriber status", skip(user, body, db_pool))]
+pub async fn update_subscriber_status(
+    user: Authorized<ManageSubscribers>,
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<UpdateStatusBody>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber =
+        set_subscriber_status(user.user_id, *subscriber_id, body.status, &db_pool).await?;
+
+    Ok(HttpResponse::Ok().json(subscriber))
+}
+
+#[tracing::instrument(name = "Delete subscriber", skip(user, db_pool))]
+pub async fn delete_subscriber(
+    user: Authorized<ManageSubscribers>,
+    subscriber_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to aquire transaction")?;
+
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        *subscriber_id
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to delete subscription tokens")?;
+
+    let deleted = sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, *subscriber_id)
+        .execute(transaction.deref_mut())
+        .await
+        .context("Failed to delete subscriber")?
+        .rows_affected();
+
+    if deleted == 0 {
+        return Err(AppError::NotFound("Subscriber not found".into()));
+    }
+
+    AuditEvent::new(AuditAction::SubscriberDeleted)
+        .by(user.user_id)
+        .on(*subscriber_id)
+        .record(transaction.deref_mut())
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(name = "Fetch subscribers page", skip(query, cursor, db_pool))]
+async fn fetch_subscribers(
+    query: &ListSubscribersQuery,
+    cursor: Option<&Cursor>,
+    limit: i64,
+    db_pool: &PgPool,
+) -> Result<Vec<SubscriberRecord>, anyhow::Error> {
+    // Escape LIKE wildcards so the filter is a plain substring match
+    let email_pattern = query.email.as_ref().map(|e| {
+        e.replace('\\', r"\\")
+            .replace('%', r"\%")
+            .replace('_', r"\_")
+    });
+
+    let subscribers = sqlx::query!(
+        r#"
+    SELECT id, email, display_email, name, status AS "status: SubscriptionStatus", subscribed_at
+    FROM subscriptions
+    WHERE ($1::subscription_status IS NULL OR status = $1)
+      AND ($2::timestamptz IS NULL OR subscribed_at >= $2)
+      AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+      AND ($4::text IS NULL OR email ILIKE '%' || $4 || '%')
+      AND ($5::timestamptz IS NULL OR (subscribed_at, id) > ($5, $6::uuid))
+    ORDER BY subscribed_at, id
+    LIMIT $7
+    "#,
+        query.status as Option<SubscriptionStatus>,
+        query.subscribed_after,
+        query.subscribed_before,
+        email_pattern,
+        cursor.map(|c| c.subscribed_at),
+        cursor.map(|c| c.id),
+        limit,
+    )
+    .fetch_all(db_pool)
+    .await?
+    .into_iter()
+    .map(|r| SubscriberRecord {
+        id: r.id,
+        email: r.email,
+        display_email: r.display_email,
+        name: r.name,
+        status: r.status,
+        subscribed_at: r.subscribed_at,
+    })
+    .collect();
+
+    Ok(subscribers)
+}
+
+#[tracing::instrument(name = "Fetch subscriber", skip(db_pool))]
+async fn fetch_subscriber(
+    subscriber_id: Uuid,
+    db_pool: &PgPool,
+) -> Result<Option<SubscriberRecord>, sqlx::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+    SELECT id, email, display_email, name, status AS "status: SubscriptionStatus", subscribed_at
+    FROM subscriptions
+    WHERE id = $1
+    "#,
+        subscriber_id,
+    )
+    .fetch_optional(db_pool)
+    .await?;
+
+    Ok(subscriber)
+}
+
+#[tracing::instrument(name = "Set subscriber status", skip(db_pool))]
+async fn set_subscriber_status(
+    actor_user_id: Uuid,
+    subscriber_id: Uuid,
+    status: SubscriptionStatus,
+    db_pool: &PgPool,
+) -> Result<SubscriberRecord, AppError> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to aquire transaction")?;
+
+    change_subscription_status(&mut transaction, subscriber_id, status).await?;
+
+    AuditEvent::new(AuditAction::SubscriberStatusChanged)
+        .by(actor_user_id)
+        .on(subscriber_id)
+        .with_details(serde_json::json!({ "status": status }))
+        .record(transaction.deref_mut())
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    fetch_subscriber(subscriber_id, db_pool)
+        .await
+        .context("Failed to fetch subscriber")?
+        .ok_or_else(|| AppError::NotFound("Subscriber not found".into()))
+}
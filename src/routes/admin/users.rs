@@ -0,0 +1,322 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{reset_two_factor, Authorized, ManageUsers, PasswordHashing},
+    domain::{SubscriberEmail, UserRole},
+    error::AppError,
+    problem::FieldError,
+    users::{create_user, CreateUserError, NewUser, USERS_EMAIL_KEY},
+};
+
+#[derive(serde::Deserialize)]
+pub struct CreateUserBody {
+    username: String,
+    email: Option<String>,
+    password: Secret<String>,
+    role: UserRole,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateUserEmailBody {
+    email: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct UserRecord {
+    user_id: Uuid,
+    username: String,
+    email: Option<String>,
+    role: UserRole,
+    created_at: DateTime<Utc>,
+    disabled_at: Option<DateTime<Utc>>,
+    two_factor_enabled: bool,
+}
+
+impl From<CreateUserError> for AppError {
+    fn from(e: CreateUserError) -> Self {
+        match e {
+            CreateUserError::UsernameTaken(_) | CreateUserError::EmailTaken => {
+                AppError::Conflict(e.to_string())
+            }
+            CreateUserError::UnexpectedError(e) => AppError::UnexpectedError(e),
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Create user account",
+    skip(user, body, db_pool, password_hashing)
+)]
+pub async fn create_user_account(
+    user: Authorized<ManageUsers>,
+    body: web::Json<CreateUserBody>,
+    db_pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+) -> Result<HttpResponse, AppError> {
+    let body = body.into_inner();
+    let new_user = NewUser::parse(body.username, body.email, body.password, body.role)
+        .map_err(AppError::FieldValidationError)?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    let username = new_user.username.clone();
+    let role = new_user.role;
+    let user_id = create_user(new_user, &password_hashing, &mut transaction).await?;
+
+    AuditEvent::new(AuditAction::UserCreated)
+        .by(user.user_id)
+        .on(user_id)
+        .with_details(serde_json::json!({ "username": username, "role": role }))
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    tracing::info!(created_by = %user.user_id, %user_id, "Created user");
+
+    let created = fetch_user(user_id, db_pool.as_ref())
+        .await
+        .context("Failed to fetch created user")?
+        .context("Created user not found")?;
+
+    Ok(HttpResponse::Created().json(created))
+}
+
+#[tracing::instrument(name = "List users", skip(_user, db_pool))]
+pub async fn list_users(
+    _user: Authorized<ManageUsers>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let users = sqlx::query_as!(
+        UserRecord,
+        r#"SELECT user_id, username, email, role AS "role: UserRole", created_at, disabled_at,
+        totp_enabled_at IS NOT NULL AS "two_factor_enabled!"
+        FROM users ORDER BY created_at, username"#
+    )
+    .fetch_all(db_pool.as_ref())
+    .await
+    .context("Failed to fetch users")?;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[tracing::instrument(name = "Disable user", skip(user, db_pool))]
+pub async fn disable_user(
+    user: Authorized<ManageUsers>,
+    user_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = user_id.into_inner();
+    ensure_not_self(user.user_id, user_id, "disable")?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    // Keep the original date when disabling twice
+    sqlx::query!(
+        r#"UPDATE users SET disabled_at = COALESCE(disabled_at, $2) WHERE user_id = $1"#,
+        user_id,
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to disable user")?;
+
+    let disabled = fetch_user(user_id, &mut *transaction)
+        .await
+        .context("Failed to fetch user")?
+        .ok_or_else(|| AppError::NotFound("User not found".into()))?;
+
+    AuditEvent::new(AuditAction::UserDisabled)
+        .by(user.user_id)
+        .on(user_id)
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(disabled))
+}
+
+#[tracing::instrument(name = "Delete user", skip(user, db_pool))]
+pub async fn delete_user(
+    user: Authorized<ManageUsers>,
+    user_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = user_id.into_inner();
+    ensure_not_self(user.user_id, user_id, "delete")?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    let username = sqlx::query_scalar!(
+        r#"DELETE FROM users WHERE user_id = $1 RETURNING username"#,
+        user_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to delete user")?
+    .ok_or_else(|| AppError::NotFound("User not found".into()))?;
+
+    // The username is kept, the entry would be hard to read once it is gone
+    AuditEvent::new(AuditAction::UserDeleted)
+        .by(user.user_id)
+        .on(user_id)
+        .with_details(serde_json::json!({ "username": username }))
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Sets or clears the address password reset links are sent to.
+#[tracing::instrument(name = "Update user email", skip(user, body, db_pool))]
+pub async fn update_user_email(
+    user: Authorized<ManageUsers>,
+    user_id: web::Path<Uuid>,
+    body: web::Json<UpdateUserEmailBody>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = user_id.into_inner();
+    let email = body
+        .into_inner()
+        .email
+        .map(SubscriberEmail::parse)
+        .transpose()
+        .map_err(|message| {
+            AppError::FieldValidationError(vec![FieldError {
+                field: "email",
+                message,
+            }])
+        })?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    sqlx::query!(
+        r#"UPDATE users SET email = $2 WHERE user_id = $1"#,
+        user_id,
+        email.as_ref().map(|e| e.as_ref())
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref db_error) if db_error.constraint() == Some(USERS_EMAIL_KEY) => {
+            AppError::from(CreateUserError::EmailTaken)
+        }
+        e => {
+            AppError::UnexpectedError(anyhow::Error::new(e).context("Failed to update user email"))
+        }
+    })?;
+
+    let updated = fetch_user(user_id, &mut *transaction)
+        .await
+        .context("Failed to fetch user")?
+        .ok_or_else(|| AppError::NotFound("User not found".into()))?;
+
+    AuditEvent::new(AuditAction::UserEmailChanged)
+        .by(user.user_id)
+        .on(user_id)
+        .with_details(serde_json::json!({ "email": updated.email }))
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(updated))
+}
+
+/// Turns two-factor authentication off for a user who lost both their
+/// authenticator and recovery codes.
+#[tracing::instrument(name = "Reset user two-factor authentication", skip(user, db_pool))]
+pub async fn reset_user_two_factor(
+    user: Authorized<ManageUsers>,
+    user_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = user_id.into_inner();
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    if reset_two_factor(user_id, &mut transaction).await? {
+        tracing::warn!(reset_by = %user.user_id, %user_id, "Reset two-factor authentication");
+        AuditEvent::new(AuditAction::UserTwoFactorReset)
+            .by(user.user_id)
+            .on(user_id)
+            .record(&mut *transaction)
+            .await?;
+    }
+
+    let reset = fetch_user(user_id, &mut *transaction)
+        .await
+        .context("Failed to fetch user")?
+        .ok_or_else(|| AppError::NotFound("User not found".into()))?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(reset))
+}
+
+/// Admins cannot lock themselves out, which could leave no admin at all.
+fn ensure_not_self(current_user_id: Uuid, user_id: Uuid, action: &str) -> Result<(), AppError> {
+    if current_user_id == user_id {
+        return Err(AppError::Conflict(format!(
+            "You cannot {} your own account",
+            action
+        )));
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Fetch user", skip(executor))]
+async fn fetch_user<'c>(
+    user_id: Uuid,
+    executor: impl PgExecutor<'c>,
+) -> Result<Option<UserRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        UserRecord,
+        r#"SELECT user_id, username, email, role AS "role: UserRole", created_at, disabled_at,
+        totp_enabled_at IS NOT NULL AS "two_factor_enabled!"
+        FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_optional(executor)
+    .await
+}
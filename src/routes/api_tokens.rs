@@ -0,0 +1,270 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{authenticate_with_password, generate_api_token, ApiTokenScope},
+    error::AppError,
+    problem::FieldError,
+};
+
+const API_TOKENS_REALM: &str = "api-tokens";
+const MAX_TOKEN_NAME_LENGTH: usize = 100;
+
+#[derive(serde::Deserialize)]
+pub struct CreateApiTokenBody {
+    name: String,
+    scopes: Vec<ApiTokenScope>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ApiTokenRecord {
+    id: Uuid,
+    name: String,
+    scopes: Vec<ApiTokenScope>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+/// A freshly created token, the only time its value is returned.
+#[derive(serde::Serialize)]
+pub struct CreatedApiToken {
+    #[serde(flatten)]
+    record: ApiTokenRecord,
+    token: String,
+}
+
+/// API tokens can only be managed with a password, so a leaked token
+/// cannot be used to mint new ones.
+#[tracing::instrument(
+    name = "Authenticate API token owner",
+    skip(request, db_pool),
+    fields(user_id=tracing::field::Empty)
+)]
+async fn authenticate_owner(request: &HttpRequest, db_pool: &PgPool) -> Result<Uuid, AppError> {
+    let user_id = authenticate_with_password(request, db_pool)
+        .await
+        .map_err(|e| AppError::from_auth_error(API_TOKENS_REALM, e))?;
+
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    Ok(user_id)
+}
+
+#[tracing::instrument(name = "Create API token", skip(body, db_pool, request))]
+pub async fn create_api_token(
+    body: web::Json<CreateApiTokenBody>,
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let user_id = authenticate_owner(&request, &db_pool).await?;
+    let body = body.into_inner();
+    let now = Utc::now();
+
+    validate_new_token(&body, now).map_err(AppError::FieldValidationError)?;
+
+    let (token, token_hash) = generate_api_token();
+    let record = ApiTokenRecord {
+        id: Uuid::new_v4(),
+        name: body.name.trim().to_string(),
+        scopes: dedup_scopes(body.scopes),
+        created_at: now,
+        expires_at: body.expires_at,
+        last_used_at: None,
+    };
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    insert_api_token(&record, user_id, &token_hash, &mut transaction)
+        .await
+        .context("Failed to store API token")?;
+
+    AuditEvent::new(AuditAction::ApiTokenCreated)
+        .by(user_id)
+        .on(record.id)
+        .with_details(serde_json::json!({
+            "name": record.name,
+            "scopes": record.scopes,
+            "expires_at": record.expires_at,
+        }))
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Created().json(CreatedApiToken {
+        record,
+        token: token.expose_secret().clone(),
+    }))
+}
+
+#[tracing::instrument(name = "List API tokens", skip(db_pool, request))]
+pub async fn list_api_tokens(
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let user_id = authenticate_owner(&request, &db_pool).await?;
+
+    let tokens = fetch_api_tokens(user_id, &db_pool)
+        .await
+        .context("Failed to fetch API tokens")?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[tracing::instrument(name = "Revoke API token", skip(db_pool, request))]
+pub async fn revoke_api_token(
+    token_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let user_id = authenticate_owner(&request, &db_pool).await?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    let result = sqlx::query!(
+        r#"DELETE FROM api_tokens WHERE id = $1 AND user_id = $2"#,
+        *token_id,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete API token")?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound("API token not found".into()));
+    }
+
+    AuditEvent::new(AuditAction::ApiTokenRevoked)
+        .by(user_id)
+        .on(*token_id)
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn validate_new_token(
+    body: &CreateApiTokenBody,
+    now: DateTime<Utc>,
+) -> Result<(), Vec<FieldError>> {
+    let mut errors = vec![];
+    let name = body.name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_TOKEN_NAME_LENGTH {
+        errors.push(FieldError {
+            field: "name",
+            message: format!(
+                "name must be between 1 and {} characters",
+                MAX_TOKEN_NAME_LENGTH
+            ),
+        });
+    }
+
+    if body.scopes.is_empty() {
+        errors.push(FieldError {
+            field: "scopes",
+            message: "At least one scope is required".into(),
+        });
+    }
+
+    if body.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        errors.push(FieldError {
+            field: "expires_at",
+            message: "expires_at must be in the future".into(),
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn dedup_scopes(scopes: Vec<ApiTokenScope>) -> Vec<ApiTokenScope> {
+    scopes.into_iter().fold(vec![], |mut acc, scope| {
+        if !acc.contains(&scope) {
+            acc.push(scope);
+        }
+        acc
+    })
+}
+
+#[tracing::instrument(name = "Insert API token", skip(record, token_hash, transaction))]
+async fn insert_api_token(
+    record: &ApiTokenRecord,
+    user_id: Uuid,
+    token_hash: &str,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    let scopes: Vec<String> = record.scopes.iter().map(|s| s.as_str().into()).collect();
+
+    sqlx::query!(
+        r#"INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        record.id,
+        user_id,
+        record.name,
+        token_hash,
+        &scopes,
+        record.created_at,
+        record.expires_at
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Fetch API tokens", skip(db_pool))]
+async fn fetch_api_tokens(
+    user_id: Uuid,
+    db_pool: &PgPool,
+) -> Result<Vec<ApiTokenRecord>, anyhow::Error> {
+    sqlx::query!(
+        r#"SELECT id, name, scopes, created_at, expires_at, last_used_at
+        FROM api_tokens WHERE user_id = $1 ORDER BY created_at, id"#,
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await?
+    .into_iter()
+    .map(|r| {
+        let scopes = r
+            .scopes
+            .iter()
+            .map(|s| ApiTokenScope::parse(s).map_err(anyhow::Error::msg))
+            .collect::<Result<_, _>>()?;
+
+        Ok(ApiTokenRecord {
+            id: r.id,
+            name: r.name,
+            scopes,
+            created_at: r.created_at,
+            expires_at: r.expires_at,
+            last_used_at: r.last_used_at,
+        })
+    })
+    .collect()
+}
@@ -1,5 +1,31 @@
-use actix_web::{HttpResponse, Responder};
+use actix_web::{web, HttpResponse, Responder};
+use sqlx::PgPool;
+
+use crate::{email_client::EmailClient, health::HealthChecker};
 
 pub async fn health_check() -> impl Responder {
     HttpResponse::Ok().body("Pong!")
 }
+
+/// The process is up and serving requests, whatever its dependencies.
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "alive" }))
+}
+
+/// The application can do its job: its dependencies answer and the schema
+/// is up to date. Answers 503 with the failing components otherwise.
+#[tracing::instrument(name = "Check readiness", skip(health_checker, db_pool, email_client))]
+pub async fn readiness(
+    health_checker: web::Data<HealthChecker>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+) -> HttpResponse {
+    let readiness = health_checker.readiness(&db_pool, &email_client).await;
+
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        tracing::warn!(?readiness, "Not ready");
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
@@ -0,0 +1,31 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{Authorized, ViewStats},
+    error::AppError,
+    metrics::Metrics,
+};
+
+#[tracing::instrument(name = "Export metrics", skip(metrics, db_pool))]
+pub async fn export_metrics(
+    metrics: web::Data<Metrics>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let body = metrics.render(&db_pool)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
+
+/// `GET /metrics` on the application listener, which is public, so only
+/// users allowed to view stats may scrape it there.
+#[tracing::instrument(name = "Export metrics to a user", skip(user, metrics, db_pool), fields(user_id = %user.user_id))]
+pub async fn export_metrics_to_user(
+    user: Authorized<ViewStats>,
+    metrics: web::Data<Metrics>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    export_metrics(metrics, db_pool).await
+}
@@ -1,9 +1,19 @@
+mod admin;
+mod api_tokens;
 mod health_check;
+mod metrics;
 mod newsletter;
+mod password_reset;
 mod subscriptions;
 mod subscriptions_confirm;
+mod two_factor;
 
+pub use admin::*;
+pub use api_tokens::*;
 pub use health_check::*;
+pub use metrics::*;
 pub use newsletter::*;
+pub use password_reset::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use two_factor::*;
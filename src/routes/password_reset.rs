@@ -0,0 +1,325 @@
+use actix_web::{
+    http::header::{self, ContentType},
+    web, HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{LoginThrottle, PasswordHashing},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    error::AppError,
+    json_or_form::{is_json, JsonOrForm},
+    problem::FieldError,
+    rate_limit::RateLimiter,
+    request_id::with_current_request_id,
+    shutdown::BackgroundTasks,
+    startup::ApplicationBaseUrl,
+    users::validate_password,
+};
+
+const PASSWORD_RESET_TOKEN_LENGTH: usize = 32;
+const PASSWORD_RESET_TOKEN_TTL_MINUTES: i64 = 60;
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetBody {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetLinkParams {
+    token: Secret<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmPasswordResetBody {
+    token: Secret<String>,
+    password: Secret<String>,
+}
+
+/// Emails a password reset link to the user with this address.
+///
+/// The response is the same whether a user matches or not, and the link
+/// is sent in the background so that the response time does not tell
+/// either.
+#[tracing::instrument(
+    name = "Request password reset",
+    skip(body, db_pool, email_client, rate_limiter, base_url, background_tasks)
+)]
+pub async fn request_password_reset(
+    body: web::Json<PasswordResetBody>,
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    rate_limiter: web::Data<RateLimiter>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    background_tasks: web::Data<BackgroundTasks>,
+) -> Result<HttpResponse, AppError> {
+    let email = SubscriberEmail::parse(body.into_inner().email).map_err(|message| {
+        AppError::FieldValidationError(vec![FieldError {
+            field: "email",
+            message,
+        }])
+    })?;
+
+    rate_limiter.check_reset_email(email.as_ref()).await?;
+
+    background_tasks.spawn(with_current_request_id(
+        async move {
+            if let Err(e) =
+                send_password_reset_link(email, &db_pool, &email_client, &base_url.0).await
+            {
+                tracing::error!(error.cause_chain = ?e, "Failed to send password reset link");
+            }
+        }
+        .instrument(tracing::Span::current()),
+    ));
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Page opened by the link of a password reset email, a form posting the
+/// token and a new password to `POST /password-reset/confirm`.
+#[tracing::instrument(name = "Show password reset form", skip(params, db_pool))]
+pub async fn password_reset_form(
+    params: web::Query<PasswordResetLinkParams>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, AppError> {
+    let token = params.into_inner().token;
+
+    // Only tokens we could have generated are echoed back in the page
+    let well_formed = token.expose_secret().len() == PASSWORD_RESET_TOKEN_LENGTH
+        && token
+            .expose_secret()
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric());
+    if !well_formed
+        || !is_password_reset_token_usable(token.expose_secret(), &db_pool)
+            .await
+            .context("Failed to check password reset token")?
+    {
+        return Err(invalid_token_error());
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        // The page address holds the token
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .insert_header((header::REFERRER_POLICY, "no-referrer"))
+        .body(format!(
+            r#"<!doctype html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Reset your password</title></head>
+<body>
+<form action="/password-reset/confirm" method="post">
+<input type="hidden" name="token" value="{}">
+<label>New password <input type="password" name="password" autocomplete="new-password" required></label>
+<button type="submit">Set password</button>
+</form>
+</body>
+</html>"#,
+            token.expose_secret()
+        )))
+}
+
+/// Sets a new password with the token of a password reset link.
+///
+/// Takes a JSON document or the form of `GET /password-reset/confirm`,
+/// which is answered with a page instead of an empty response.
+#[tracing::instrument(
+    name = "Confirm password reset",
+    skip(body, request, db_pool, password_hashing, login_throttle),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn confirm_password_reset(
+    body: JsonOrForm<ConfirmPasswordResetBody>,
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashing>,
+    login_throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, AppError> {
+    let body = body.into_inner();
+    validate_password(&body.password).map_err(|e| AppError::FieldValidationError(vec![e]))?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    let user_id = sqlx::query_scalar!(
+        r#"UPDATE password_reset_tokens SET used_at = $2
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > $2
+        RETURNING user_id"#,
+        hash_password_reset_token(body.token.expose_secret()),
+        Utc::now()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to use password reset token")?
+    .ok_or_else(invalid_token_error)?;
+
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let password_hash = password_hashing.hash(body.password).await?;
+
+    let username = sqlx::query_scalar!(
+        r#"UPDATE users SET password_hash = $2 WHERE user_id = $1 RETURNING username"#,
+        user_id,
+        password_hash.expose_secret()
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to update password")?;
+
+    // Links sent before this one must not be usable any more
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete password reset tokens")?;
+
+    AuditEvent::new(AuditAction::PasswordReset)
+        .by(user_id)
+        .on(user_id)
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    // The new password lifts a lockout caused by the forgotten one
+    login_throttle.record_success(&username, &db_pool).await?;
+
+    tracing::info!("Password reset");
+
+    if is_json(&request) {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body("<!doctype html>\n<p>Your password has been changed.</p>"))
+    }
+}
+
+fn invalid_token_error() -> AppError {
+    AppError::ValidationError("The password reset token is invalid or has expired".into())
+}
+
+#[tracing::instrument(name = "Check password reset token", skip(token, db_pool))]
+async fn is_password_reset_token_usable(
+    token: &str,
+    db_pool: &PgPool,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS(
+            SELECT 1 FROM password_reset_tokens
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > $2
+        ) AS "usable!""#,
+        hash_password_reset_token(token),
+        Utc::now()
+    )
+    .fetch_one(db_pool)
+    .await
+}
+
+#[tracing::instrument(
+    name = "Send password reset link",
+    skip(email, db_pool, email_client, base_url)
+)]
+async fn send_password_reset_link(
+    email: SubscriberEmail,
+    db_pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
+    let Some(user_id) = sqlx::query_scalar!(
+        r#"SELECT user_id FROM users WHERE lower(email) = lower($1) AND disabled_at IS NULL"#,
+        email.as_ref()
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to fetch user by email")?
+    else {
+        tracing::info!("No active user with this email, no password reset link sent");
+        return Ok(());
+    };
+
+    let token = generate_password_reset_token();
+    save_password_reset_token(user_id, &token, db_pool)
+        .await
+        .context("Failed to save password reset token")?;
+
+    let reset_link = format!(
+        "{}/password-reset/confirm?token={}",
+        base_url,
+        token.expose_secret()
+    );
+
+    email_client
+        .send_email(
+            &email,
+            "Reset your password",
+            &format!(
+                r#"Click <a href="{}">here</a> to choose a new password.<br/>The link expires in {} minutes, ignore this email if you did not ask for it."#,
+                reset_link, PASSWORD_RESET_TOKEN_TTL_MINUTES
+            ),
+            &format!(
+                "Visit {} to choose a new password.\nThe link expires in {} minutes, ignore this email if you did not ask for it.",
+                reset_link, PASSWORD_RESET_TOKEN_TTL_MINUTES
+            ),
+        )
+        .await
+        .context("Failed to send password reset email")?;
+
+    tracing::info!(%user_id, "Sent password reset link");
+
+    Ok(())
+}
+
+fn generate_password_reset_token() -> Secret<String> {
+    let token = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(PASSWORD_RESET_TOKEN_LENGTH)
+        .collect();
+
+    Secret::new(token)
+}
+
+/// Like API tokens, reset tokens are long random strings stored hashed.
+fn hash_password_reset_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[tracing::instrument(name = "Save password reset token", skip(token, db_pool))]
+async fn save_password_reset_token(
+    user_id: Uuid,
+    token: &Secret<String>,
+    db_pool: &PgPool,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+
+    sqlx::query!(
+        r#"INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)"#,
+        hash_password_reset_token(token.expose_secret()),
+        user_id,
+        now,
+        now + Duration::minutes(PASSWORD_RESET_TOKEN_TTL_MINUTES)
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
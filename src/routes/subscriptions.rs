@@ -1,9 +1,6 @@
 use std::ops::DerefMut;
 
-use actix_web::{
-    web::{self, Form},
-    HttpResponse, ResponseError,
-};
+use actix_web::{web, HttpResponse};
 use anyhow::Context;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use secrecy::ExposeSecret;
@@ -11,34 +8,66 @@ use sqlx::{types::chrono::Utc, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    deliverability::DeliverabilityChecker,
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus},
     email_client::EmailClient,
+    error::{error_chain_fmt, AppError},
+    json_or_form::JsonOrForm,
+    metrics::Metrics,
+    problem::FieldError,
+    rate_limit::RateLimiter,
     startup::ApplicationBaseUrl,
 };
 
 #[derive(serde::Deserialize)]
-pub struct FormData {
+pub struct SubscriberData {
     name: String,
     email: String,
 }
+
 pub struct SaveTokenError(sqlx::Error);
 
-#[derive(thiserror::Error)]
-pub enum SubscribeError {
+#[derive(thiserror::Error, Debug)]
+pub enum StatusChangeError {
+    #[error("Subscriber not found")]
+    NotFound,
+
     #[error("{0}")]
-    ValidationError(String),
+    IllegalTransition(String),
 
     #[error(transparent)]
-    UnexpectedError(#[from] anyhow::Error),
+    UnexpectedError(#[from] sqlx::Error),
 }
 
-impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+impl From<StatusChangeError> for AppError {
+    fn from(e: StatusChangeError) -> Self {
+        match e {
+            StatusChangeError::NotFound => AppError::NotFound(e.to_string()),
+            StatusChangeError::IllegalTransition(message) => AppError::Conflict(message),
+            StatusChangeError::UnexpectedError(_) => AppError::UnexpectedError(
+                anyhow::Error::new(e).context("Failed to change subscription status"),
+            ),
+        }
+    }
+}
 
-    fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { name, email })
+impl TryFrom<SubscriberData> for NewSubscriber {
+    type Error = Vec<FieldError>;
+
+    fn try_from(value: SubscriberData) -> Result<Self, Self::Error> {
+        let name = SubscriberName::parse(value.name).map_err(|message| FieldError {
+            field: "name",
+            message,
+        });
+        let email = SubscriberEmail::parse(value.email).map_err(|message| FieldError {
+            field: "email",
+            message,
+        });
+
+        match (name, email) {
+            (Ok(name), Ok(email)) => Ok(Self { name, email }),
+            (name, email) => Err(name.err().into_iter().chain(email.err()).collect()),
+        }
     }
 }
 
@@ -54,64 +83,86 @@ impl std::fmt::Debug for SaveTokenError {
     }
 }
 
-impl ResponseError for SaveTokenError {}
-
-impl From<String> for SubscribeError {
-    fn from(e: String) -> Self {
-        Self::ValidationError(e)
-    }
-}
-
 impl std::error::Error for SaveTokenError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         Some(&self.0)
     }
 }
 
-impl std::fmt::Debug for SubscribeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        error_chain_fmt(self, f)
-    }
-}
-
-impl ResponseError for SubscribeError {
-    fn status_code(&self) -> actix_web::http::StatusCode {
-        match self {
-            SubscribeError::ValidationError(_) => actix_web::http::StatusCode::BAD_REQUEST,
-            SubscribeError::UnexpectedError(_) => {
-                actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
-            }
-        }
-    }
-}
-
 #[tracing::instrument(
     name = "Saving a new subscriber",
-    skip(form, db_pool, email_client, base_url),
-    fields(
-        subs_name = %form.name,
-        email = %form.email
-    )
+    skip(body, db_pool, email_client, deliverability_checker, rate_limiter, base_url, metrics),
+    fields(subs_name = tracing::field::Empty, email = tracing::field::Empty)
 )]
 pub async fn subscribe(
-    form: Form<FormData>,
+    body: JsonOrForm<SubscriberData>,
     db_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
+    deliverability_checker: web::Data<DeliverabilityChecker>,
+    rate_limiter: web::Data<RateLimiter>,
     base_url: web::Data<ApplicationBaseUrl>,
-) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber: NewSubscriber = form.0.try_into()?;
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, AppError> {
+    let subscriber_data = body.into_inner();
 
-    let token = generate_subscription_token();
+    tracing::Span::current()
+        .record("subs_name", tracing::field::display(&subscriber_data.name))
+        .record("email", tracing::field::display(&subscriber_data.email));
+
+    let new_subscriber: NewSubscriber = subscriber_data
+        .try_into()
+        .map_err(AppError::FieldValidationError)?;
+
+    rate_limiter
+        .check_subscription_email(new_subscriber.email.as_ref())
+        .await?;
+
+    deliverability_checker
+        .check(&new_subscriber.email)
+        .await
+        .map_err(|e| {
+            AppError::FieldValidationError(vec![FieldError {
+                field: "email",
+                message: e.to_string(),
+            }])
+        })?;
 
     let mut transaction = db_pool
         .begin()
         .await
         .context("Failed to aquire transaction")?;
 
-    let subscriber_id = insert_subscriber(&new_subscriber, &mut transaction)
+    // Signing up again answers like a first signup, so the endpoint does
+    // not tell whether an address is subscribed
+    let existing = find_subscriber_by_email(&new_subscriber.email, &mut transaction)
         .await
-        .context("Failed to insert subscriber")?;
+        .context("Failed to look for subscriber")?;
+    let (subscriber_id, is_new) = match existing {
+        None => match insert_subscriber(&new_subscriber, &mut transaction)
+            .await
+            .context("Failed to insert subscriber")?
+        {
+            Some(subscriber_id) => (subscriber_id, true),
+            // A concurrent signup for the same address sends the link
+            None => return Ok(HttpResponse::Ok().finish()),
+        },
+        Some((_, SubscriptionStatus::Confirmed)) => {
+            tracing::info!("Subscriber already confirmed");
+            return Ok(HttpResponse::Ok().finish());
+        }
+        Some((subscriber_id, SubscriptionStatus::PendingConfirmation)) => (subscriber_id, false),
+        Some((subscriber_id, SubscriptionStatus::Unsubscribed)) => {
+            change_subscription_status(
+                &mut transaction,
+                subscriber_id,
+                SubscriptionStatus::PendingConfirmation,
+            )
+            .await?;
+            (subscriber_id, false)
+        }
+    };
 
+    let token = generate_subscription_token();
     save_token(subscriber_id, &token, &mut transaction)
         .await
         .context("Failed to save token")?;
@@ -131,10 +182,35 @@ pub async fn subscribe(
     .await
     .context("Failed to send confirmation link")?;
 
+    if is_new {
+        metrics.subscriptions_created_total.inc();
+    }
     tracing::info!("Subscriber save success");
     Ok(HttpResponse::Ok().finish())
 }
 
+/// Locks and returns the id and status of the subscriber using `email`.
+#[tracing::instrument(name = "Looking up subscriber by email", skip(email, transaction))]
+async fn find_subscriber_by_email(
+    email: &SubscriberEmail,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<(Uuid, SubscriptionStatus)>, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+    SELECT id, status AS "status: SubscriptionStatus"
+    FROM subscriptions
+    WHERE lower(email) = lower($1)
+    FOR UPDATE
+    "#,
+        email.as_ref()
+    )
+    .fetch_optional(transaction.deref_mut())
+    .await?;
+
+    Ok(subscriber.map(|s| (s.id, s.status)))
+}
+
+/// Returns `None`, saving nothing, when the address is already subscribed.
 #[tracing::instrument(
     name = "Saving subscriber in db"
     skip(new_subscriber, transaction)
@@ -142,27 +218,100 @@ pub async fn subscribe(
 pub async fn insert_subscriber(
     new_subscriber: &NewSubscriber,
     transaction: &mut Transaction<'_, Postgres>,
-) -> Result<uuid::Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
+) -> Result<Option<uuid::Uuid>, sqlx::Error> {
+    let subscriber_id = sqlx::query_scalar!(
+        r#"
+    INSERT INTO subscriptions(id, email, display_email, name, subscribed_at, status)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    ON CONFLICT DO NOTHING
+    RETURNING id
+    "#,
+        Uuid::new_v4(),
+        new_subscriber.email.as_ref(),
+        new_subscriber.email.display_form(),
+        new_subscriber.name.as_ref(),
+        Utc::now(),
+        SubscriptionStatus::PendingConfirmation as SubscriptionStatus,
+    )
+    .fetch_optional(transaction.deref_mut())
+    .await?;
+
+    let Some(subscriber_id) = subscriber_id else {
+        return Ok(None);
+    };
+
+    record_status_change(
+        transaction,
+        subscriber_id,
+        None,
+        SubscriptionStatus::PendingConfirmation,
+    )
+    .await?;
+
+    Ok(Some(subscriber_id))
+}
+
+/// Moves a subscriber to `next`, enforcing the transitions allowed by
+/// `SubscriptionStatus::transition_to` and recording the change in the
+/// status history. Returns false when the subscriber already had `next`.
+#[tracing::instrument(name = "Change subscription status", skip(transaction))]
+pub async fn change_subscription_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    next: SubscriptionStatus,
+) -> Result<bool, StatusChangeError> {
+    let current = sqlx::query!(
+        r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(transaction.deref_mut())
+    .await?
+    .ok_or(StatusChangeError::NotFound)?
+    .status;
+
+    current
+        .transition_to(next)
+        .map_err(StatusChangeError::IllegalTransition)?;
+
+    if current == next {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = $2 WHERE id = $1"#,
+        subscriber_id,
+        next as SubscriptionStatus,
+    )
+    .execute(transaction.deref_mut())
+    .await?;
+
+    record_status_change(transaction, subscriber_id, Some(current), next).await?;
+
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Record subscription status change", skip(transaction))]
+async fn record_status_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    from: Option<SubscriptionStatus>,
+    to: SubscriptionStatus,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"
-    INSERT INTO subscriptions(id, email, name, subscribed_at, status)
+    INSERT INTO subscription_status_history(id, subscriber_id, from_status, to_status, changed_at)
     VALUES ($1, $2, $3, $4, $5)
     "#,
+        Uuid::new_v4(),
         subscriber_id,
-        new_subscriber.email.as_ref(),
-        new_subscriber.name.as_ref(),
+        from as Option<SubscriptionStatus>,
+        to as SubscriptionStatus,
         Utc::now(),
-        "PENDING_CONFIRMATION",
     )
     .execute(transaction.deref_mut())
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {}", e);
-        e
-    })?;
+    .await?;
 
-    Ok(subscriber_id)
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -231,18 +380,3 @@ pub async fn save_token(
 
     Ok(())
 }
-
-fn error_chain_fmt(
-    e: &impl std::error::Error,
-    f: &mut std::fmt::Formatter<'_>,
-) -> std::fmt::Result {
-    writeln!(f, "{}\n", e)?;
-
-    let mut current = e.source();
-    while let Some(cause) = current {
-        writeln!(f, "Caused By:\n\t{}", cause)?;
-        current = cause.source();
-    }
-
-    Ok(())
-}
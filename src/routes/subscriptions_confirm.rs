@@ -1,46 +1,54 @@
 use std::ops::DerefMut;
 
 use actix_web::{web, HttpResponse};
-use sqlx::{Executor, PgPool, Postgres, Transaction};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::{
+    domain::SubscriptionStatus, error::AppError, metrics::Metrics,
+    routes::change_subscription_status,
+};
+
 #[derive(serde::Deserialize)]
 pub struct SubConfirmationParam {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm pending subscriber", skip(param, db_pool))]
+#[tracing::instrument(name = "Confirm pending subscriber", skip(param, db_pool, metrics))]
 pub async fn confirm(
     param: web::Query<SubConfirmationParam>,
     db_pool: web::Data<PgPool>,
-) -> HttpResponse {
-    let mut transaction = match db_pool.begin().await {
-        Ok(t) => t,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
+    metrics: web::Data<Metrics>,
+) -> Result<HttpResponse, AppError> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to aquire transaction")?;
 
-    let subscriber_id =
-        match get_subscriber_id_from_token(&mut transaction, &param.subscription_token).await {
-            Ok(id) => id,
-            Err(_) => return HttpResponse::InternalServerError().finish(),
-        };
+    let subscriber_id = get_subscriber_id_from_token(&mut transaction, &param.subscription_token)
+        .await
+        .context("Failed to get subscriber id from token")?
+        .ok_or_else(|| AppError::NotFound("Unknown subscription token".into()))?;
 
-    if subscriber_id.is_none() {
-        return HttpResponse::NotFound().finish();
-    }
+    let confirmed = change_subscription_status(
+        &mut transaction,
+        subscriber_id,
+        SubscriptionStatus::Confirmed,
+    )
+    .await?;
 
-    if set_subscriber_status_to_confirmed(&mut transaction, subscriber_id.unwrap())
+    transaction
+        .commit()
         .await
-        .is_err()
-    {
-        return HttpResponse::InternalServerError().finish();
-    }
+        .context("Failed to commit transaction")?;
 
-    if transaction.commit().await.is_err() {
-        return HttpResponse::InternalServerError().finish();
+    // Following the link again is fine, but it is not a new confirmation
+    if confirmed {
+        metrics.subscriptions_confirmed_total.inc();
     }
 
-    HttpResponse::Ok().finish()
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[tracing::instrument(name = "Get subscriber id from token", skip(transaction, token))]
@@ -53,29 +61,7 @@ async fn get_subscriber_id_from_token(
         token
     )
     .fetch_optional(transaction.deref_mut())
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .await?;
 
     Ok(result.map(|r| r.subscriber_id))
 }
-
-#[tracing::instrument(name = "Set subscriber status to confirmed", skip(transaction))]
-async fn set_subscriber_status_to_confirmed(
-    transaction: &mut Transaction<'_, Postgres>,
-    subscriber_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    let query = sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'CONFIRMED' WHERE id = $1"#,
-        subscriber_id
-    );
-
-    transaction.execute(query).await.map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-
-    Ok(())
-}
@@ -0,0 +1,249 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::{
+        authenticate_with_password, generate_totp_secret, provisioning_uri, replace_recovery_codes,
+        reset_two_factor, verify_totp_code,
+    },
+    error::AppError,
+};
+
+const TWO_FACTOR_REALM: &str = "two-factor";
+
+#[derive(serde::Serialize)]
+pub struct TwoFactorEnrolment {
+    secret: String,
+    provisioning_uri: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmTwoFactorBody {
+    code: String,
+}
+
+/// Recovery codes, the only time they are returned.
+#[derive(serde::Serialize)]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+impl RecoveryCodes {
+    fn new(codes: Vec<Secret<String>>) -> Self {
+        Self {
+            recovery_codes: codes.iter().map(|c| c.expose_secret().clone()).collect(),
+        }
+    }
+}
+
+struct TwoFactorState {
+    username: String,
+    totp_secret: Option<String>,
+    totp_enabled_at: Option<DateTime<Utc>>,
+}
+
+/// Two-factor settings are managed with a password, plus the current
+/// second factor once it is enabled.
+#[tracing::instrument(
+    name = "Authenticate two-factor owner",
+    skip(request, db_pool),
+    fields(user_id=tracing::field::Empty)
+)]
+async fn authenticate_owner(request: &HttpRequest, db_pool: &PgPool) -> Result<Uuid, AppError> {
+    let user_id = authenticate_with_password(request, db_pool)
+        .await
+        .map_err(|e| AppError::from_auth_error(TWO_FACTOR_REALM, e))?;
+
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    Ok(user_id)
+}
+
+/// Starts enrolment with a new secret, replacing any unconfirmed one.
+#[tracing::instrument(name = "Start two-factor enrolment", skip(db_pool, request))]
+pub async fn start_two_factor_enrolment(
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let user_id = authenticate_owner(&request, &db_pool).await?;
+    let state = fetch_two_factor_state(user_id, &db_pool).await?;
+
+    if state.totp_enabled_at.is_some() {
+        return Err(AppError::Conflict(
+            "Two-factor authentication is already enabled".into(),
+        ));
+    }
+
+    let secret = generate_totp_secret();
+    let provisioning_uri = provisioning_uri(&secret, &state.username)?;
+
+    sqlx::query!(
+        r#"UPDATE users SET totp_secret = $2, totp_last_step = NULL
+        WHERE user_id = $1 AND totp_enabled_at IS NULL"#,
+        user_id,
+        secret.expose_secret()
+    )
+    .execute(db_pool.as_ref())
+    .await
+    .context("Failed to store TOTP secret")?;
+
+    Ok(HttpResponse::Created().json(TwoFactorEnrolment {
+        secret: secret.expose_secret().clone(),
+        provisioning_uri,
+    }))
+}
+
+/// Enables two-factor authentication once the user proves their
+/// authenticator app produces valid codes.
+#[tracing::instrument(name = "Confirm two-factor enrolment", skip(body, db_pool, request))]
+pub async fn confirm_two_factor_enrolment(
+    body: web::Json<ConfirmTwoFactorBody>,
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let user_id = authenticate_owner(&request, &db_pool).await?;
+    let state = fetch_two_factor_state(user_id, &db_pool).await?;
+
+    if state.totp_enabled_at.is_some() {
+        return Err(AppError::Conflict(
+            "Two-factor authentication is already enabled".into(),
+        ));
+    }
+    let secret = state
+        .totp_secret
+        .map(Secret::new)
+        .ok_or_else(|| AppError::Conflict("Two-factor enrolment has not been started".into()))?;
+
+    let step = verify_totp_code(&secret, &state.username, &body.code, None)?
+        .ok_or_else(|| AppError::ValidationError("Invalid two-factor code".into()))?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    // Guarded against the secret being replaced by a concurrent enrolment
+    let result = sqlx::query!(
+        r#"UPDATE users SET totp_enabled_at = $2, totp_last_step = $3
+        WHERE user_id = $1 AND totp_secret = $4 AND totp_enabled_at IS NULL"#,
+        user_id,
+        Utc::now(),
+        step,
+        secret.expose_secret()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enable two-factor authentication")?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::Conflict(
+            "Two-factor enrolment changed, start it again".into(),
+        ));
+    }
+
+    let recovery_codes = replace_recovery_codes(user_id, &mut transaction).await?;
+
+    AuditEvent::new(AuditAction::TwoFactorEnabled)
+        .by(user_id)
+        .on(user_id)
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    tracing::info!("Enabled two-factor authentication");
+
+    Ok(HttpResponse::Ok().json(RecoveryCodes::new(recovery_codes)))
+}
+
+#[tracing::instrument(name = "Regenerate recovery codes", skip(db_pool, request))]
+pub async fn regenerate_recovery_codes(
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let user_id = authenticate_owner(&request, &db_pool).await?;
+    let state = fetch_two_factor_state(user_id, &db_pool).await?;
+
+    if state.totp_enabled_at.is_none() {
+        return Err(AppError::NotFound(
+            "Two-factor authentication is not enabled".into(),
+        ));
+    }
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    let recovery_codes = replace_recovery_codes(user_id, &mut transaction).await?;
+
+    AuditEvent::new(AuditAction::RecoveryCodesRegenerated)
+        .by(user_id)
+        .on(user_id)
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodes::new(recovery_codes)))
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(db_pool, request))]
+pub async fn disable_two_factor(
+    db_pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let user_id = authenticate_owner(&request, &db_pool).await?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    if !reset_two_factor(user_id, &mut transaction).await? {
+        return Err(AppError::NotFound(
+            "Two-factor authentication is not enabled".into(),
+        ));
+    }
+
+    AuditEvent::new(AuditAction::TwoFactorDisabled)
+        .by(user_id)
+        .on(user_id)
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    tracing::info!("Disabled two-factor authentication");
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(name = "Fetch two-factor state", skip(db_pool))]
+async fn fetch_two_factor_state(
+    user_id: Uuid,
+    db_pool: &PgPool,
+) -> Result<TwoFactorState, anyhow::Error> {
+    sqlx::query_as!(
+        TwoFactorState,
+        r#"SELECT username, totp_secret, totp_enabled_at FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(db_pool)
+    .await
+    .context("Failed to fetch two-factor state")
+}
@@ -0,0 +1,93 @@
+use std::{future::Future, time::Duration};
+
+use tokio::runtime::Handle;
+use tokio_util::task::TaskTracker;
+
+/// Work spawned off requests, such as emails sent after responding, that
+/// shutdown waits for.
+///
+/// Tasks run on the runtime the tracker was created on rather than on the
+/// worker's, which actix drops, along with its tasks, when it stops.
+#[derive(Clone)]
+pub struct BackgroundTasks {
+    tracker: TaskTracker,
+    runtime: Handle,
+}
+
+impl BackgroundTasks {
+    /// Panics outside of a Tokio runtime.
+    pub fn on_current_runtime() -> Self {
+        Self {
+            tracker: TaskTracker::new(),
+            runtime: Handle::current(),
+        }
+    }
+
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tracker.spawn_on(future, &self.runtime);
+    }
+
+    /// Waits up to `timeout` for the running tasks, returning whether they
+    /// all finished. Tasks spawned afterwards are not waited for.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.tracker.close();
+        tokio::time::timeout(timeout, self.tracker.wait())
+            .await
+            .is_ok()
+    }
+}
+
+/// Completes on SIGINT or SIGTERM.
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => tracing::info!("Received SIGINT"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::BackgroundTasks;
+
+    #[tokio::test]
+    async fn drain_waits_for_running_tasks() {
+        let tasks = BackgroundTasks::on_current_runtime();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tasks.spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            tx.send(()).unwrap();
+        });
+
+        assert!(tasks.drain(Duration::from_secs(5)).await);
+        assert!(rx.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn drain_gives_up_after_the_timeout() {
+        let tasks = BackgroundTasks::on_current_runtime();
+        tasks.spawn(std::future::pending());
+
+        assert!(!tasks.drain(Duration::from_millis(50)).await);
+    }
+}
@@ -1,33 +1,138 @@
-use std::net::TcpListener;
+use std::{
+    future::Future,
+    net::TcpListener,
+    ops::DerefMut,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use actix_web::{dev::Server, web, App, HttpServer};
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use actix_web::{dev::Server, middleware::from_fn, web, App, HttpServer};
+use anyhow::Context;
+use sqlx::{migrate::Migrator, postgres::PgPoolOptions, PgPool, Postgres, Transaction};
 use tracing_actix_web::TracingLogger;
+use uuid::Uuid;
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
+    audit::{AuditAction, AuditEvent},
+    authentication::{LoginThrottle, PasswordHashing},
+    client_ip::TrustForwardedFor,
+    configuration::{ApplicationSettings, DatabaseSettings, Settings},
+    deliverability::{DeliverabilityChecker, DnsMxResolver, MxResolver},
+    domain::{SubscriberEmail, SubscriptionStatus},
     email_client::EmailClient,
-    routes,
+    health::{self, HealthChecker},
+    metrics::{self, Metrics},
+    problem,
+    rate_limit::{self, RateLimiter},
+    request_id, routes,
+    shutdown::{shutdown_signal, BackgroundTasks},
+    telemetry::RequestSpanBuilder,
+    users,
 };
 pub struct Application {
     pub port: u16,
     pub server: Server,
+    /// Port of the separate metrics listener, if configured.
+    pub metrics_port: Option<u16>,
+    metrics_server: Option<Server>,
+    db_pool: PgPool,
+    background_tasks: BackgroundTasks,
+    shutdown_grace_period: Duration,
 }
 impl Application {
     pub async fn build(config: Settings, db_pool: PgPool) -> Result<Self, std::io::Error> {
         let address = format!("{}:{}", config.application.host, config.application.port);
         let listener = TcpListener::bind(address).expect("Failed to bind to port");
 
+        let metrics = Metrics::new().map_err(std::io::Error::other)?;
+
         let email_client = EmailClient::new(
             config.email.base_url.as_str(),
             config.email.sender(),
             config.email.authorization_token.clone(),
             config.email.timeout(),
+        )
+        .with_metrics(metrics.clone());
+
+        let mx_resolver = if config.deliverability.check_mx_records {
+            let resolver = DnsMxResolver::from_system_conf().map_err(std::io::Error::other)?;
+            Some(Arc::new(resolver) as Arc<dyn MxResolver>)
+        } else {
+            None
+        };
+
+        let deliverability_checker = DeliverabilityChecker::new(
+            &config.deliverability.disposable_domains,
+            config.deliverability.reject_role_addresses,
+            mx_resolver,
         );
 
+        let rate_limiter = RateLimiter::from_settings(&config.rate_limit, db_pool.clone());
+        let login_throttle = LoginThrottle::new(&config.login_throttle);
+        let password_hashing =
+            PasswordHashing::new(&config.password_hashing).map_err(std::io::Error::other)?;
+
+        if config.database.run_migrations_on_startup {
+            run_migrations(&db_pool)
+                .await
+                .map_err(std::io::Error::other)?;
+        }
+
+        users::bootstrap_admin(config.bootstrap_admin.as_ref(), &password_hashing, &db_pool)
+            .await
+            .map_err(std::io::Error::other)?;
+
+        // Metrics get their own listener when a port is configured for them
+        let metrics_listener = match config.metrics.port {
+            Some(port) if config.metrics.enabled => {
+                let address = format!("{}:{}", config.application.host, port);
+                Some(TcpListener::bind(address)?)
+            }
+            _ => None,
+        };
+        let metrics_port = metrics_listener
+            .as_ref()
+            .map(|listener| listener.local_addr().map(|address| address.port()))
+            .transpose()?;
+        let metrics_server = metrics_listener
+            .map(|listener| {
+                run_metrics(
+                    listener,
+                    db_pool.clone(),
+                    metrics.clone(),
+                    config.application.shutdown_grace_period(),
+                )
+            })
+            .transpose()?;
+        let serve_metrics = config.metrics.enabled && metrics_server.is_none();
+        let health_checker = HealthChecker::new(&config.health, &MIGRATOR);
+        let background_tasks = BackgroundTasks::on_current_runtime();
+        let shutdown_grace_period = config.application.shutdown_grace_period();
+
         Ok(Self {
             port: listener.local_addr().unwrap().port(),
-            server: run(listener, db_pool, email_client, config.application.base_url).await?,
+            server: run(
+                listener,
+                db_pool.clone(),
+                Services {
+                    email_client,
+                    deliverability_checker,
+                    rate_limiter,
+                    login_throttle,
+                    password_hashing,
+                    metrics,
+                    health_checker,
+                    background_tasks: background_tasks.clone(),
+                },
+                config.application,
+                serve_metrics,
+            )
+            .await?,
+            metrics_port,
+            metrics_server,
+            db_pool,
+            background_tasks,
+            shutdown_grace_period,
         })
     }
 
@@ -35,9 +140,230 @@ impl Application {
         self.port
     }
 
+    /// Serves until SIGINT or SIGTERM, then shuts down gracefully.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        self.run_until(shutdown_signal()).await
     }
+
+    /// Serves until `shutdown` completes, then stops accepting connections
+    /// and lets in-flight requests and background tasks finish within the
+    /// grace period before closing the connection pool.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> Result<(), std::io::Error> {
+        let Application {
+            server,
+            metrics_server,
+            db_pool,
+            background_tasks,
+            shutdown_grace_period,
+            ..
+        } = self;
+
+        let handles: Vec<_> = std::iter::once(server.handle())
+            .chain(metrics_server.as_ref().map(Server::handle))
+            .collect();
+        let servers = async move {
+            match metrics_server {
+                Some(metrics_server) => tokio::try_join!(server, metrics_server).map(|_| ()),
+                None => server.await,
+            }
+        };
+        tokio::pin!(servers);
+
+        let outcome = tokio::select! {
+            outcome = &mut servers => outcome,
+            _ = shutdown => {
+                tracing::info!(
+                    grace_period_seconds = shutdown_grace_period.as_secs(),
+                    "Shutting down"
+                );
+                let deadline = Instant::now() + shutdown_grace_period;
+                // The stop command is sent right away, the returned future
+                // only waits for it to complete, which `servers` does too.
+                for handle in handles {
+                    drop(handle.stop(true));
+                }
+                let outcome = servers.await;
+
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if !background_tasks.drain(remaining).await {
+                    tracing::warn!("Background tasks did not finish within the grace period");
+                }
+                outcome
+            }
+        };
+
+        db_pool.close().await;
+        tracing::info!("Shut down");
+
+        outcome
+    }
+}
+
+/// Migrations embedded at build time, so a binary knows which schema it
+/// expects.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Applies the pending migrations, then converts stored subscriber emails
+/// to the form new signups are stored in.
+///
+/// The migrator holds a Postgres advisory lock, keyed on the database, for
+/// the duration of the run, so replicas starting together wait for each
+/// other instead of applying the same migration twice.
+#[tracing::instrument(name = "Run migrations", skip(db_pool))]
+pub async fn run_migrations(db_pool: &PgPool) -> Result<(), anyhow::Error> {
+    let applied_before = health::applied_migrations(db_pool).await?;
+
+    MIGRATOR
+        .run(db_pool)
+        .await
+        .context("Failed to apply migrations")?;
+
+    let applied: Vec<i64> = health::applied_migrations(db_pool)
+        .await?
+        .into_iter()
+        .filter(|version| !applied_before.contains(version))
+        .collect();
+    if !applied.is_empty() {
+        AuditEvent::new(AuditAction::MigrationsApplied)
+            .with_details(serde_json::json!({ "versions": applied }))
+            .record(db_pool)
+            .await?;
+    }
+
+    normalize_stored_emails(db_pool).await?;
+
+    tracing::info!(applied = applied.len(), "Database schema is up to date");
+
+    Ok(())
+}
+
+/// Rewrites stored addresses with a non-ASCII domain to punycode, which the
+/// migration normalising emails cannot compute in SQL. Until then the unique
+/// email index cannot tell them apart from the same address signed up again.
+///
+/// When the converted address belongs to another subscriber, only one of
+/// them is kept, picked like the migration does: an unsubscribed one, else
+/// a confirmed one, else the oldest.
+#[tracing::instrument(name = "Normalize stored subscriber emails", skip(db_pool))]
+async fn normalize_stored_emails(db_pool: &PgPool) -> Result<(), anyhow::Error> {
+    let candidates = sqlx::query_scalar!(
+        r#"SELECT id FROM subscriptions WHERE substring(email FROM '@([^@]*)$') ~ '[^\x01-\x7f]'"#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch subscribers with a non-ASCII email domain")?;
+
+    let (mut normalized, mut merged, mut invalid) = (0, 0, 0);
+    for subscriber_id in candidates {
+        let mut transaction = db_pool
+            .begin()
+            .await
+            .context("Failed to acquire transaction")?;
+
+        // Gone if it was merged into an earlier candidate
+        let Some(subscriber) = sqlx::query!(
+            r#"SELECT email, status AS "status: SubscriptionStatus", subscribed_at
+            FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+            subscriber_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to fetch subscriber")?
+        else {
+            continue;
+        };
+
+        let email = match SubscriberEmail::parse(subscriber.email.clone()) {
+            Ok(email) if email.as_ref() != subscriber.email => email,
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::warn!(%subscriber_id, error = %e, "Cannot normalize stored email");
+                invalid += 1;
+                continue;
+            }
+        };
+
+        let other = sqlx::query!(
+            r#"SELECT id, status AS "status: SubscriptionStatus", subscribed_at
+            FROM subscriptions WHERE lower(email) = lower($1) AND id <> $2 FOR UPDATE"#,
+            email.as_ref(),
+            subscriber_id
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to fetch subscriber with the normalized email")?;
+
+        let keep_rank = |status, subscribed_at| {
+            (
+                status == SubscriptionStatus::Unsubscribed,
+                status == SubscriptionStatus::Confirmed,
+                std::cmp::Reverse(subscribed_at),
+            )
+        };
+        match other {
+            Some(other)
+                if keep_rank(other.status, other.subscribed_at)
+                    >= keep_rank(subscriber.status, subscriber.subscribed_at) =>
+            {
+                delete_duplicate(&mut transaction, subscriber_id, other.id).await?;
+                merged += 1;
+            }
+            other => {
+                if let Some(other) = other {
+                    delete_duplicate(&mut transaction, other.id, subscriber_id).await?;
+                    merged += 1;
+                }
+                sqlx::query!(
+                    r#"UPDATE subscriptions SET email = $2 WHERE id = $1"#,
+                    subscriber_id,
+                    email.as_ref()
+                )
+                .execute(&mut *transaction)
+                .await
+                .context("Failed to normalize email")?;
+                normalized += 1;
+            }
+        }
+
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit transaction")?;
+    }
+
+    if normalized + merged + invalid > 0 {
+        tracing::info!(normalized, merged, invalid, "Normalized stored emails");
+    }
+
+    Ok(())
+}
+
+/// Deletes a subscriber found to share its address with `kept_id`.
+async fn delete_duplicate(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    kept_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(transaction.deref_mut())
+    .await
+    .context("Failed to delete subscription tokens")?;
+
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(transaction.deref_mut())
+        .await
+        .context("Failed to delete subscriber")?;
+
+    AuditEvent::new(AuditAction::SubscriberDeleted)
+        .on(subscriber_id)
+        .with_details(serde_json::json!({ "duplicate_of": kept_id }))
+        .record(transaction.deref_mut())
+        .await?;
+
+    Ok(())
 }
 
 pub fn get_connection_pool(db_config: &DatabaseSettings) -> PgPool {
@@ -46,28 +372,179 @@ pub fn get_connection_pool(db_config: &DatabaseSettings) -> PgPool {
 
 pub struct ApplicationBaseUrl(pub String);
 
+/// State shared by the request handlers.
+pub struct Services {
+    pub email_client: EmailClient,
+    pub deliverability_checker: DeliverabilityChecker,
+    pub rate_limiter: RateLimiter,
+    pub login_throttle: LoginThrottle,
+    pub password_hashing: PasswordHashing,
+    pub metrics: Metrics,
+    pub health_checker: HealthChecker,
+    pub background_tasks: BackgroundTasks,
+}
+
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
-    base_url: String,
+    services: Services,
+    application: ApplicationSettings,
+    serve_metrics: bool,
 ) -> Result<Server, std::io::Error> {
     // wrap connection in smart pointer
     let db_connection_pool = web::Data::new(db_pool);
-    let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let email_client = web::Data::new(services.email_client);
+    let deliverability_checker = web::Data::new(services.deliverability_checker);
+    let rate_limiter = web::Data::new(services.rate_limiter);
+    let login_throttle = web::Data::new(services.login_throttle);
+    let password_hashing = web::Data::new(services.password_hashing);
+    let metrics = web::Data::new(services.metrics);
+    let health_checker = web::Data::new(services.health_checker);
+    let background_tasks = web::Data::new(services.background_tasks);
+    let shutdown_grace_period = application.shutdown_grace_period();
+    let base_url = web::Data::new(ApplicationBaseUrl(application.base_url));
+    let trust_forwarded_for = web::Data::new(TrustForwardedFor(application.trust_forwarded_for));
 
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap(from_fn(metrics::record_http_metrics))
+            .wrap(from_fn(problem::add_request_id))
+            .wrap(TracingLogger::<RequestSpanBuilder>::new())
+            .wrap(from_fn(request_id::propagate_request_id))
             .route("/ping", web::get().to(routes::health_check))
-            .route("/subscriptions", web::post().to(routes::subscribe))
-            .route("/subscriptions/confirm", web::get().to(routes::confirm))
+            .route("/health/live", web::get().to(routes::liveness))
+            .route("/health/ready", web::get().to(routes::readiness))
+            .configure(|cfg| {
+                if serve_metrics {
+                    cfg.route("/metrics", web::get().to(routes::export_metrics_to_user));
+                }
+            })
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(from_fn(rate_limit::limit_by_ip))
+                    .route(web::post().to(routes::subscribe)),
+            )
+            .service(
+                web::resource("/subscriptions/confirm")
+                    .wrap(from_fn(rate_limit::limit_by_ip))
+                    .route(web::get().to(routes::confirm)),
+            )
+            .service(
+                web::resource("/password-reset")
+                    .wrap(from_fn(rate_limit::limit_by_ip))
+                    .route(web::post().to(routes::request_password_reset)),
+            )
+            .service(
+                web::resource("/password-reset/confirm")
+                    .wrap(from_fn(rate_limit::limit_by_ip))
+                    .route(web::get().to(routes::password_reset_form))
+                    .route(web::post().to(routes::confirm_password_reset)),
+            )
             .route("/newsletter", web::post().to(routes::publish_newsletter))
+            .service(
+                web::scope("/api-tokens")
+                    .route("", web::post().to(routes::create_api_token))
+                    .route("", web::get().to(routes::list_api_tokens))
+                    .route("/{token_id}", web::delete().to(routes::revoke_api_token)),
+            )
+            .service(
+                web::scope("/two-factor")
+                    .route("", web::post().to(routes::start_two_factor_enrolment))
+                    .route("", web::delete().to(routes::disable_two_factor))
+                    .route(
+                        "/confirm",
+                        web::post().to(routes::confirm_two_factor_enrolment),
+                    )
+                    .route(
+                        "/recovery-codes",
+                        web::post().to(routes::regenerate_recovery_codes),
+                    ),
+            )
+            .service(
+                web::scope("/admin")
+                    .route("/audit", web::get().to(routes::list_audit_log))
+                    .route("/stats", web::get().to(routes::get_stats))
+                    .route("/users", web::post().to(routes::create_user_account))
+                    .route("/users", web::get().to(routes::list_users))
+                    .route("/users/{user_id}", web::delete().to(routes::delete_user))
+                    .route(
+                        "/users/{user_id}/email",
+                        web::put().to(routes::update_user_email),
+                    )
+                    .route(
+                        "/users/{user_id}/two-factor",
+                        web::delete().to(routes::reset_user_two_factor),
+                    )
+                    .route(
+                        "/users/{user_id}/disable",
+                        web::post().to(routes::disable_user),
+                    )
+                    .route("/subscribers", web::get().to(routes::list_subscribers))
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(routes::get_subscriber_by_id),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::delete().to(routes::delete_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        web::post().to(routes::confirm_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/status",
+                        web::put().to(routes::update_subscriber_status),
+                    ),
+            )
+            .default_service(web::to(problem::not_found))
+            .app_data(web::JsonConfig::default().error_handler(problem::extractor_error_handler))
+            .app_data(web::FormConfig::default().error_handler(problem::extractor_error_handler))
+            .app_data(web::QueryConfig::default().error_handler(problem::extractor_error_handler))
+            .app_data(web::PathConfig::default().error_handler(problem::extractor_error_handler))
             .app_data(db_connection_pool.clone())
             .app_data(email_client.clone())
+            .app_data(deliverability_checker.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(login_throttle.clone())
+            .app_data(password_hashing.clone())
             .app_data(base_url.clone())
+            .app_data(trust_forwarded_for.clone())
+            .app_data(metrics.clone())
+            .app_data(health_checker.clone())
+            .app_data(background_tasks.clone())
+    })
+    // Signals are handled by `Application::run_until_stopped`
+    .disable_signals()
+    .shutdown_timeout(shutdown_grace_period.as_secs())
+    .listen(listener)?
+    .run();
+
+    Ok(server)
+}
+
+/// Serves only `GET /metrics`, on a listener separate from the application.
+pub fn run_metrics(
+    listener: TcpListener,
+    db_pool: PgPool,
+    metrics: Metrics,
+    shutdown_grace_period: Duration,
+) -> Result<Server, std::io::Error> {
+    let db_pool = web::Data::new(db_pool);
+    let metrics = web::Data::new(metrics);
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(TracingLogger::<RequestSpanBuilder>::new())
+            .wrap(from_fn(request_id::propagate_request_id))
+            .route("/metrics", web::get().to(routes::export_metrics))
+            .default_service(web::to(problem::not_found))
+            .app_data(db_pool.clone())
+            .app_data(metrics.clone())
     })
+    .workers(1)
+    .disable_signals()
+    .shutdown_timeout(shutdown_grace_period.as_secs())
     .listen(listener)?
     .run();
 
@@ -1,24 +1,257 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    HttpMessage,
+};
+use anyhow::Context;
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    trace::TracerProvider,
+};
+use opentelemetry_otlp::{Protocol, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use tokio::task::JoinHandle;
-use tracing::{subscriber::set_global_default, Subscriber};
+use tracing::{field::Empty, subscriber::set_global_default, Span, Subscriber};
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt::MakeWriter,
+    layer::{Layered, SubscriberExt},
+    EnvFilter, Layer, Registry,
+};
 
+use crate::{
+    configuration::{
+        LogFileSettings, LogFormat, LogRotation, LoggingSettings, OtlpProtocol, TracingSettings,
+    },
+    request_id::RequestId,
+};
+
+type FormattingLayer = Box<dyn Layer<Layered<EnvFilter, Registry>> + Send + Sync>;
+
+/// Builds the subscriber logging to `sink` as configured, also exporting
+/// spans with `tracer` when given.
 pub fn get_subscriber<Sink>(
     name: String,
-    env_filter: String,
+    settings: &LoggingSettings,
     sink: Sink,
-) -> impl Subscriber + Send + Sync
+    tracer: Option<SdkTracer>,
+) -> Result<impl Subscriber + Send + Sync, anyhow::Error>
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
-    let env_filter =
-        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
-    let formatting_layer = BunyanFormattingLayer::new(name, sink);
-    Registry::default()
+    let env_filter = match EnvFilter::try_from_default_env() {
+        Ok(env_filter) => env_filter,
+        Err(_) => EnvFilter::try_new(filter_directives(settings))
+            .context("Invalid log level or module override")?,
+    };
+
+    let mut layers: Vec<FormattingLayer> = vec![];
+    if settings.format == LogFormat::Bunyan {
+        // Shared by every Bunyan layer, it may only be added once
+        layers.push(Box::new(JsonStorageLayer));
+    }
+    layers.push(formatting_layer(settings.format, &name, sink, true));
+    if let Some(file) = &settings.file {
+        layers.push(formatting_layer(
+            settings.format,
+            &name,
+            rolling_file(file)?,
+            false,
+        ));
+    }
+
+    let otel_layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+    Ok(Registry::default()
         .with(env_filter)
-        .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(layers)
+        .with(otel_layer))
+}
+
+/// `EnvFilter` directives of the default level and module overrides.
+fn filter_directives(settings: &LoggingSettings) -> String {
+    std::iter::once(settings.level.clone())
+        .chain(
+            settings
+                .modules
+                .iter()
+                .map(|(module, level)| format!("{}={}", module, level)),
+        )
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn formatting_layer<W>(format: LogFormat, name: &str, writer: W, ansi: bool) -> FormattingLayer
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Bunyan => Box::new(BunyanFormattingLayer::new(name.to_string(), writer)),
+        LogFormat::Pretty => Box::new(
+            tracing_subscriber::fmt::layer()
+                .pretty()
+                .with_ansi(ansi)
+                .with_writer(writer),
+        ),
+        LogFormat::Logfmt => Box::new(tracing_logfmt::builder().layer().with_writer(writer)),
+    }
+}
+
+fn rolling_file(settings: &LogFileSettings) -> Result<RollingFileAppender, anyhow::Error> {
+    let rotation = match settings.rotation {
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+
+    RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(&settings.prefix)
+        .build(&settings.directory)
+        .with_context(|| format!("Failed to open log file in {}", settings.directory))
+}
+
+/// Builds the provider exporting spans over OTLP, `None` when export is
+/// disabled.
+///
+/// The provider is also installed globally along with the W3C trace
+/// context propagator, so that `traceparent` headers are read from incoming
+/// requests and written to outbound ones. Shut it down before exiting to
+/// flush the last batch of spans.
+pub fn init_tracer_provider(
+    settings: &TracingSettings,
+) -> Result<Option<SdkTracerProvider>, anyhow::Error> {
+    if !settings.otlp_enabled {
+        return Ok(None);
+    }
+
+    let protocol = match settings.otlp_protocol {
+        OtlpProtocol::Protobuf => Protocol::HttpBinary,
+        OtlpProtocol::Json => Protocol::HttpJson,
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_protocol(protocol)
+        .with_endpoint(settings.otlp_endpoint.clone())
+        .build()
+        .context("Failed to build OTLP span exporter")?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(settings.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(provider.clone());
+
+    Ok(Some(provider))
+}
+
+/// Root span of HTTP requests, tagged with our `RequestId` rather than the
+/// one `TracingLogger` generates, and continuing the caller's trace.
+pub struct RequestSpanBuilder;
+
+impl RootSpanBuilder for RequestSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        let method = request.method().as_str();
+        let route = request
+            .match_pattern()
+            .unwrap_or_else(|| "default".to_string());
+        let connection_info = request.connection_info();
+        let user_agent = request
+            .headers()
+            .get("User-Agent")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("");
+
+        let span = tracing::info_span!(
+            "HTTP request",
+            http.method = %method,
+            http.route = %route,
+            http.client_ip = %connection_info.realip_remote_addr().unwrap_or(""),
+            http.user_agent = %user_agent,
+            http.target = %request.uri().path_and_query().map(|p| p.as_str()).unwrap_or(""),
+            http.status_code = Empty,
+            otel.name = %format!("{} {}", method, route),
+            otel.kind = "server",
+            otel.status_code = Empty,
+            request_id = %request_id,
+            exception.message = Empty,
+            exception.details = Empty,
+        );
+
+        let parent_context = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(request.headers()))
+        });
+        // Fails only when spans are not exported, there is no trace to join then
+        let _ = span.set_parent(parent_context);
+
+        span
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+struct HeaderExtractor<'a>(&'a actix_web::http::header::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// The tracer `get_subscriber` exports spans with.
+pub fn tracer(provider: &SdkTracerProvider) -> SdkTracer {
+    provider.tracer("zero2prod")
+}
+
+/// Adds the trace context of the current span to outbound request headers,
+/// a no-op unless spans are exported.
+pub fn inject_trace_context(headers: &mut HeaderMap) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
@@ -34,3 +267,208 @@ where
     let current_span = tracing::Span::current();
     tokio::task::spawn_blocking(move || current_span.in_scope(f))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use opentelemetry::global;
+    use secrecy::Secret;
+    use tracing::Instrument;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{get_subscriber, init_tracer_provider, tracer};
+    use crate::{
+        configuration::{
+            LogFileSettings, LogFormat, LogRotation, LoggingSettings, OtlpProtocol, TracingSettings,
+        },
+        domain::SubscriberEmail,
+        email_client::EmailClient,
+    };
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a50e0e8ff0c2b5a1";
+
+    fn logging_settings(format: LogFormat) -> LoggingSettings {
+        LoggingSettings {
+            format,
+            level: "info".into(),
+            modules: Default::default(),
+            file: None,
+        }
+    }
+
+    /// In-memory sink, to read back what was logged.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Buffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_with(settings: &LoggingSettings) -> String {
+        let buffer = Buffer::default();
+        let sink = buffer.clone();
+        let subscriber =
+            get_subscriber("test".into(), settings, move || sink.clone(), None).unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "zero2prod_rust::routes", subscriber_count = 3, "Published");
+            tracing::debug!(target: "zero2prod_rust::rate_limit", "Bucket refilled");
+            tracing::info!(target: "sqlx::query", "SELECT 1");
+        });
+
+        buffer.contents()
+    }
+
+    #[test]
+    fn every_format_can_be_selected() {
+        let bunyan = log_with(&logging_settings(LogFormat::Bunyan));
+        let line: serde_json::Value = serde_json::from_str(bunyan.lines().next().unwrap()).unwrap();
+        assert_eq!(line["msg"], "Published");
+        assert_eq!(line["subscriber_count"], 3);
+
+        let pretty = log_with(&logging_settings(LogFormat::Pretty));
+        assert!(pretty.contains("Published"));
+        assert!(pretty.contains("subscriber_count"));
+        assert!(serde_json::from_str::<serde_json::Value>(pretty.lines().next().unwrap()).is_err());
+
+        let logfmt = log_with(&logging_settings(LogFormat::Logfmt));
+        let line = logfmt.lines().next().unwrap();
+        assert!(line.contains("level=info"));
+        assert!(line.contains("message=Published"));
+        assert!(line.contains("subscriber_count=3"));
+    }
+
+    #[test]
+    fn module_levels_override_the_default_level() {
+        let mut settings = logging_settings(LogFormat::Logfmt);
+        settings
+            .modules
+            .insert("zero2prod_rust::rate_limit".into(), "debug".into());
+        settings.modules.insert("sqlx".into(), "warn".into());
+
+        let logs = log_with(&settings);
+
+        assert!(logs.contains("Published"));
+        assert!(logs.contains("Bucket refilled"));
+        assert!(!logs.contains("SELECT 1"));
+    }
+
+    #[test]
+    fn invalid_module_levels_are_rejected() {
+        let mut settings = logging_settings(LogFormat::Bunyan);
+        settings.modules.insert("sqlx".into(), "loud".into());
+
+        assert!(get_subscriber("test".into(), &settings, std::io::sink, None).is_err());
+    }
+
+    #[test]
+    fn logs_are_also_written_to_files() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let mut settings = logging_settings(LogFormat::Logfmt);
+        settings.file = Some(LogFileSettings {
+            directory: directory.to_string_lossy().into(),
+            prefix: "zero2prod.log".into(),
+            rotation: LogRotation::Never,
+        });
+
+        log_with(&settings);
+
+        let logged = std::fs::read_to_string(directory.join("zero2prod.log")).unwrap();
+        assert!(logged.contains("message=Published"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn incoming_trace_is_exported_and_propagated_to_the_email_api() {
+        // Stand-in for an OpenTelemetry collector
+        let collector = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&collector)
+            .await;
+        let email_server = MockServer::start().await;
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&email_server)
+            .await;
+
+        let settings = TracingSettings {
+            otlp_enabled: true,
+            otlp_endpoint: format!("{}/v1/traces", collector.uri()),
+            otlp_protocol: OtlpProtocol::Json,
+            service_name: "zero2prod-test".into(),
+        };
+        let provider = init_tracer_provider(&settings).unwrap().unwrap();
+        let subscriber = get_subscriber(
+            "test".into(),
+            &logging_settings(LogFormat::Bunyan),
+            std::io::sink,
+            Some(tracer(&provider)),
+        )
+        .unwrap();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let email_client = EmailClient::new(
+            &email_server.uri(),
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            Secret::new("token".into()),
+            std::time::Duration::from_secs(1),
+        );
+        let incoming_headers = HashMap::from([(
+            "traceparent".to_string(),
+            format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+        )]);
+        let span = tracing::info_span!("Publish a newsletter issue");
+        span.set_parent(global::get_text_map_propagator(|propagator| {
+            propagator.extract(&incoming_headers)
+        }))
+        .unwrap();
+
+        email_client
+            .send_email(
+                &SubscriberEmail::parse("reader@example.com".into()).unwrap(),
+                "Subject",
+                "<p>Content</p>",
+                "Content",
+            )
+            .instrument(span)
+            .await
+            .unwrap();
+        provider.force_flush().unwrap();
+
+        let email_requests = email_server.received_requests().await.unwrap();
+        let traceparent = email_requests[0].headers.get("traceparent").unwrap();
+        assert!(traceparent
+            .to_str()
+            .unwrap()
+            .starts_with(&format!("00-{}-", TRACE_ID)));
+
+        let exported = collector.received_requests().await.unwrap();
+        let body = String::from_utf8_lossy(&exported[0].body);
+        assert!(body.contains(TRACE_ID));
+        assert!(body.contains("Publish a newsletter issue"));
+        assert!(body.contains("zero2prod-test"));
+    }
+}
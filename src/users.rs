@@ -0,0 +1,272 @@
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audit::{AuditAction, AuditEvent},
+    authentication::PasswordHashing,
+    configuration::BootstrapAdminSettings,
+    domain::{SubscriberEmail, UserRole},
+    problem::FieldError,
+};
+
+const MAX_USERNAME_LENGTH: usize = 64;
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+/// Unique index on the lowercased email of users.
+pub const USERS_EMAIL_KEY: &str = "users_email_key";
+
+/// A validated user, ready to be stored.
+#[derive(Debug)]
+pub struct NewUser {
+    pub username: String,
+    /// Where password reset links are sent.
+    pub email: Option<SubscriberEmail>,
+    pub password: Secret<String>,
+    pub role: UserRole,
+}
+
+impl NewUser {
+    pub fn parse(
+        username: String,
+        email: Option<String>,
+        password: Secret<String>,
+        role: UserRole,
+    ) -> Result<NewUser, Vec<FieldError>> {
+        let mut errors = vec![];
+        let username = username.trim().to_string();
+
+        if username.is_empty()
+            || username.chars().count() > MAX_USERNAME_LENGTH
+            || username.chars().any(|c| c == ':' || c.is_whitespace())
+        {
+            // `:` separates the username from the password in Basic auth
+            errors.push(FieldError {
+                field: "username",
+                message: format!(
+                    "username must be between 1 and {} characters, without spaces or ':'",
+                    MAX_USERNAME_LENGTH
+                ),
+            });
+        }
+
+        let email = match email.map(SubscriberEmail::parse).transpose() {
+            Ok(email) => email,
+            Err(message) => {
+                errors.push(FieldError {
+                    field: "email",
+                    message,
+                });
+                None
+            }
+        };
+
+        if let Err(e) = validate_password(&password) {
+            errors.push(e);
+        }
+
+        if errors.is_empty() {
+            Ok(NewUser {
+                username,
+                email,
+                password,
+                role,
+            })
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Checks the length of a new password.
+pub fn validate_password(password: &Secret<String>) -> Result<(), FieldError> {
+    let password_length = password.expose_secret().chars().count();
+
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&password_length) {
+        return Err(FieldError {
+            field: "password",
+            message: format!(
+                "password must be between {} and {} characters",
+                MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CreateUserError {
+    #[error("Username {0} is already taken")]
+    UsernameTaken(String),
+
+    #[error("The email is already used by another user")]
+    EmailTaken,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// Stores `new_user` with a freshly hashed password and returns its id.
+#[tracing::instrument(
+    name = "Create user",
+    skip(new_user, password_hashing, transaction),
+    fields(username = %new_user.username, role = %new_user.role)
+)]
+pub async fn create_user(
+    new_user: NewUser,
+    password_hashing: &PasswordHashing,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Uuid, CreateUserError> {
+    let user_id = Uuid::new_v4();
+    let password_hash = password_hashing.hash(new_user.password).await?;
+
+    sqlx::query!(
+        r#"INSERT INTO users (user_id, username, email, password_hash, role, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)"#,
+        user_id,
+        new_user.username,
+        new_user.email.as_ref().map(|e| e.as_ref()),
+        password_hash.expose_secret(),
+        new_user.role as UserRole,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref db_error) if db_error.constraint() == Some(USERS_EMAIL_KEY) => {
+            CreateUserError::EmailTaken
+        }
+        sqlx::Error::Database(ref db_error) if db_error.is_unique_violation() => {
+            CreateUserError::UsernameTaken(new_user.username.clone())
+        }
+        e => {
+            CreateUserError::UnexpectedError(anyhow::Error::new(e).context("Failed to insert user"))
+        }
+    })?;
+
+    Ok(user_id)
+}
+
+/// Creates the configured admin when there is no user yet, so a fresh
+/// deployment can be administered without raw SQL.
+#[tracing::instrument(
+    name = "Bootstrap admin user",
+    skip(settings, password_hashing, db_pool)
+)]
+pub async fn bootstrap_admin(
+    settings: Option<&BootstrapAdminSettings>,
+    password_hashing: &PasswordHashing,
+    db_pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let Some(settings) = settings else {
+        return Ok(());
+    };
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire transaction")?;
+
+    // Serialise concurrent starts so only one of them creates the admin
+    sqlx::query!("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to lock users table")?;
+
+    let has_users = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM users) AS "exists!""#)
+        .fetch_one(&mut *transaction)
+        .await
+        .context("Failed to count users")?;
+
+    if has_users {
+        return Ok(());
+    }
+
+    let new_user = NewUser::parse(
+        settings.username.clone(),
+        None,
+        settings.password.clone(),
+        UserRole::Admin,
+    )
+    .map_err(|errors| {
+        let messages: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+        anyhow::anyhow!("Invalid bootstrap admin: {}", messages.join(", "))
+    })?;
+
+    let user_id = create_user(new_user, password_hashing, &mut transaction).await?;
+
+    AuditEvent::new(AuditAction::AdminBootstrapped)
+        .on(user_id)
+        .with_details(serde_json::json!({ "username": settings.username }))
+        .record(&mut *transaction)
+        .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction")?;
+
+    tracing::info!(%user_id, username = %settings.username, "Created initial admin user");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    use super::NewUser;
+    use crate::domain::UserRole;
+
+    fn parse(username: &str, password: &str) -> Result<NewUser, Vec<crate::problem::FieldError>> {
+        NewUser::parse(
+            username.into(),
+            None,
+            Secret::new(password.into()),
+            UserRole::Editor,
+        )
+    }
+
+    #[test]
+    fn valid_user_is_accepted() {
+        let user = assert_ok!(parse(" bruce ", "a-long-enough-password"));
+        assert_eq!(user.username, "bruce");
+    }
+
+    #[test]
+    fn invalid_usernames_are_rejected() {
+        for username in ["", "bru ce", "bruce:wayne", &"a".repeat(65)] {
+            let errors = assert_err!(parse(username, "a-long-enough-password"));
+            assert_eq!(errors[0].field, "username");
+        }
+    }
+
+    #[test]
+    fn email_is_optional_but_validated() {
+        let user = assert_ok!(NewUser::parse(
+            "bruce".into(),
+            Some(" Bruce@Wayne.COM ".into()),
+            Secret::new("a-long-enough-password".into()),
+            UserRole::Editor,
+        ));
+        assert_eq!(user.email.unwrap().as_ref(), "Bruce@wayne.com");
+
+        let errors = assert_err!(NewUser::parse(
+            "bruce".into(),
+            Some("not-an-email".into()),
+            Secret::new("a-long-enough-password".into()),
+            UserRole::Editor,
+        ));
+        assert_eq!(errors[0].field, "email");
+    }
+
+    #[test]
+    fn short_password_is_rejected() {
+        let errors = assert_err!(parse("bruce", "short"));
+        assert_eq!(errors[0].field, "password");
+    }
+}
@@ -0,0 +1,494 @@
+use chrono::{Duration, Utc};
+use reqwest::{Method, StatusCode};
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use zero2prod_rust::domain::SubscriptionStatus;
+
+use crate::helpers::spawn_app;
+
+#[sqlx::test]
+async fn admin_endpoints_reject_unauthenticated_requests(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let subscriber_id = uuid::Uuid::new_v4();
+
+    let requests = vec![
+        (Method::GET, "/subscribers".to_string()),
+        (Method::GET, format!("/subscribers/{}", subscriber_id)),
+        (Method::DELETE, format!("/subscribers/{}", subscriber_id)),
+        (
+            Method::POST,
+            format!("/subscribers/{}/confirm", subscriber_id),
+        ),
+        (
+            Method::PUT,
+            format!("/subscribers/{}/status", subscriber_id),
+        ),
+    ];
+
+    for (method, path) in requests {
+        let response = reqwest::Client::new()
+            .request(method.clone(), format!("{}/admin{}", app.address, path))
+            .json(&serde_json::json!({ "status": "CONFIRMED" }))
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            response.status(),
+            "{} {} did not reject an unauthenticated request",
+            method,
+            path
+        );
+        assert_eq!(
+            r#"Basic realm="admin""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
+
+#[sqlx::test]
+async fn list_subscribers_paginates_with_a_cursor(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let now = Utc::now();
+
+    for i in 0..5 {
+        app.store_subscriber(
+            &format!("user{}@example.com", i),
+            SubscriptionStatus::Confirmed,
+            now + Duration::seconds(i),
+        )
+        .await;
+    }
+
+    let mut emails = vec![];
+    let mut cursor: Option<String> = None;
+    let mut pages = 0;
+
+    loop {
+        let mut query = vec![("limit", "2".to_string())];
+        if let Some(cursor) = &cursor {
+            query.push(("cursor", cursor.clone()));
+        }
+
+        let page: serde_json::Value = app
+            .admin_request(Method::GET, "/subscribers")
+            .query(&query)
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        pages += 1;
+        for subscriber in page["subscribers"].as_array().unwrap() {
+            emails.push(subscriber["email"].as_str().unwrap().to_string());
+        }
+
+        match page["next_cursor"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+
+    assert_eq!(3, pages);
+    assert_eq!(
+        vec![
+            "user0@example.com",
+            "user1@example.com",
+            "user2@example.com",
+            "user3@example.com",
+            "user4@example.com",
+        ],
+        emails
+    );
+}
+
+#[sqlx::test]
+async fn list_subscribers_applies_filters(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let now = Utc::now();
+
+    app.store_subscriber(
+        "bruce@wayne.com",
+        SubscriptionStatus::Confirmed,
+        now - Duration::days(10),
+    )
+    .await;
+    app.store_subscriber(
+        "alfred@wayne.com",
+        SubscriptionStatus::PendingConfirmation,
+        now,
+    )
+    .await;
+    app.store_subscriber(
+        "clark_kent@dailyplanet.com",
+        SubscriptionStatus::Confirmed,
+        now,
+    )
+    .await;
+    app.store_subscriber(
+        "clarkxkent@dailyplanet.com",
+        SubscriptionStatus::Confirmed,
+        now,
+    )
+    .await;
+
+    let test_cases = vec![
+        (
+            vec![("status", "PENDING_CONFIRMATION".to_string())],
+            vec!["alfred@wayne.com"],
+        ),
+        (
+            vec![("email", "WAYNE".to_string())],
+            vec!["bruce@wayne.com", "alfred@wayne.com"],
+        ),
+        (
+            vec![("email", "clark_".to_string())],
+            vec!["clark_kent@dailyplanet.com"],
+        ),
+        (
+            vec![
+                ("status", "CONFIRMED".to_string()),
+                ("subscribed_before", (now - Duration::days(1)).to_rfc3339()),
+            ],
+            vec!["bruce@wayne.com"],
+        ),
+        (
+            vec![
+                ("subscribed_after", (now - Duration::days(1)).to_rfc3339()),
+                ("email", "wayne".to_string()),
+            ],
+            vec!["alfred@wayne.com"],
+        ),
+    ];
+
+    for (query, expected) in test_cases {
+        let page: serde_json::Value = app
+            .admin_request(Method::GET, "/subscribers")
+            .query(&query)
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let mut emails: Vec<&str> = page["subscribers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["email"].as_str().unwrap())
+            .collect();
+        emails.sort();
+        let mut expected = expected;
+        expected.sort();
+
+        assert_eq!(expected, emails, "Unexpected result for query {:?}", query);
+    }
+}
+
+#[sqlx::test]
+async fn list_subscribers_rejects_invalid_parameters(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let test_cases = vec![
+        ("cursor=not-a-cursor", "invalid cursor"),
+        ("limit=0", "limit too small"),
+        ("limit=1000", "limit too large"),
+        ("status=ACTIVE", "unknown status"),
+        ("subscribed_after=yesterday", "invalid timestamp"),
+    ];
+
+    for (query, description) in test_cases {
+        let response = app
+            .admin_request(Method::GET, &format!("/subscribers?{}", query))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            response.status(),
+            "Api did not fail with 400 BAD_REQUEST for {}",
+            description
+        );
+    }
+}
+
+#[sqlx::test]
+async fn get_subscriber_returns_the_subscriber(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let subscriber_id = app
+        .store_subscriber(
+            "bruce@wayne.com",
+            SubscriptionStatus::PendingConfirmation,
+            Utc::now(),
+        )
+        .await;
+
+    let subscriber: serde_json::Value = app
+        .admin_request(Method::GET, &format!("/subscribers/{}", subscriber_id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(subscriber["id"], subscriber_id.to_string());
+    assert_eq!(subscriber["email"], "bruce@wayne.com");
+    assert_eq!(subscriber["status"], "PENDING_CONFIRMATION");
+}
+
+#[sqlx::test]
+async fn unknown_subscriber_returns_404(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let subscriber_id = uuid::Uuid::new_v4();
+
+    let requests = vec![
+        (Method::GET, format!("/subscribers/{}", subscriber_id)),
+        (Method::DELETE, format!("/subscribers/{}", subscriber_id)),
+        (
+            Method::POST,
+            format!("/subscribers/{}/confirm", subscriber_id),
+        ),
+        (
+            Method::PUT,
+            format!("/subscribers/{}/status", subscriber_id),
+        ),
+    ];
+
+    for (method, path) in requests {
+        let response = app
+            .admin_request(method.clone(), &path)
+            .json(&serde_json::json!({ "status": "CONFIRMED" }))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            StatusCode::NOT_FOUND,
+            response.status(),
+            "{} {} did not return 404",
+            method,
+            path
+        );
+    }
+}
+
+#[sqlx::test]
+async fn confirm_subscriber_marks_the_subscriber_as_confirmed(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let subscriber_id = app
+        .store_subscriber(
+            "bruce@wayne.com",
+            SubscriptionStatus::PendingConfirmation,
+            Utc::now(),
+        )
+        .await;
+
+    let response = app
+        .admin_request(
+            Method::POST,
+            &format!("/subscribers/{}/confirm", subscriber_id),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::OK, response.status());
+
+    let saved = sqlx::query!(
+        r#"SELECT status::text AS "status!" FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "CONFIRMED");
+}
+
+#[sqlx::test]
+async fn update_subscriber_status_changes_the_status(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let subscriber_id = app
+        .store_subscriber("bruce@wayne.com", SubscriptionStatus::Confirmed, Utc::now())
+        .await;
+
+    let subscriber: serde_json::Value = app
+        .admin_request(
+            Method::PUT,
+            &format!("/subscribers/{}/status", subscriber_id),
+        )
+        .json(&serde_json::json!({ "status": "UNSUBSCRIBED" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(subscriber["status"], "UNSUBSCRIBED");
+}
+
+#[sqlx::test]
+async fn update_subscriber_status_rejects_unknown_status(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let subscriber_id = app
+        .store_subscriber("bruce@wayne.com", SubscriptionStatus::Confirmed, Utc::now())
+        .await;
+
+    let response = app
+        .admin_request(
+            Method::PUT,
+            &format!("/subscribers/{}/status", subscriber_id),
+        )
+        .json(&serde_json::json!({ "status": "ACTIVE" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+}
+
+#[sqlx::test]
+async fn delete_subscriber_removes_subscriber_and_tokens(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=Bruce%20Wayne&email=bruce%40wayne.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    let response = app
+        .admin_request(Method::DELETE, &format!("/subscribers/{}", subscriber_id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    let remaining = sqlx::query!(
+        r#"SELECT
+            (SELECT COUNT(*) FROM subscriptions) AS "subscriptions!",
+            (SELECT COUNT(*) FROM subscription_tokens) AS "tokens!""#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    assert_eq!(0, remaining.subscriptions);
+    assert_eq!(0, remaining.tokens);
+}
+
+#[sqlx::test]
+async fn illegal_status_transitions_are_rejected_with_409(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let subscriber_id = app
+        .store_subscriber(
+            "bruce@wayne.com",
+            SubscriptionStatus::Unsubscribed,
+            Utc::now(),
+        )
+        .await;
+
+    let response = app
+        .admin_request(
+            Method::POST,
+            &format!("/subscribers/{}/confirm", subscriber_id),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::CONFLICT, response.status());
+
+    let saved = sqlx::query!(
+        r#"SELECT status::text AS "status!" FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "UNSUBSCRIBED");
+}
+
+#[sqlx::test]
+async fn status_changes_are_recorded_in_history(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let subscriber_id = app
+        .store_subscriber(
+            "bruce@wayne.com",
+            SubscriptionStatus::PendingConfirmation,
+            Utc::now(),
+        )
+        .await;
+
+    for status in ["CONFIRMED", "UNSUBSCRIBED"] {
+        app.admin_request(
+            Method::PUT,
+            &format!("/subscribers/{}/status", subscriber_id),
+        )
+        .json(&serde_json::json!({ "status": status }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    }
+
+    let history = sqlx::query!(
+        r#"
+        SELECT from_status::text AS "from_status", to_status::text AS "to_status!"
+        FROM subscription_status_history
+        WHERE subscriber_id = $1
+        ORDER BY changed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+
+    let transitions: Vec<(Option<String>, String)> = history
+        .into_iter()
+        .map(|r| (r.from_status, r.to_status))
+        .collect();
+
+    assert_eq!(
+        vec![
+            (
+                Some("PENDING_CONFIRMATION".to_string()),
+                "CONFIRMED".to_string()
+            ),
+            (Some("CONFIRMED".to_string()), "UNSUBSCRIBED".to_string()),
+        ],
+        transitions
+    );
+}
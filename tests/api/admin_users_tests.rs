@@ -0,0 +1,247 @@
+use reqwest::{Method, StatusCode};
+use secrecy::Secret;
+use sqlx::PgPool;
+use zero2prod_rust::{configuration::BootstrapAdminSettings, domain::UserRole};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestUser};
+
+async fn get_stats_as(address: &str, username: &str, password: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/stats", address))
+        .basic_auth(username, Some(password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[sqlx::test]
+async fn created_user_can_authenticate_with_its_role(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = app
+        .admin_request(Method::POST, "/users")
+        .json(&serde_json::json!({
+            "username": "alfred",
+            "password": "a-long-enough-password",
+            "role": "VIEWER",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::CREATED, response.status());
+    let created: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(created["username"], "alfred");
+    assert_eq!(created["role"], "VIEWER");
+    assert!(created["disabled_at"].is_null());
+    assert!(created.get("password").is_none());
+
+    let password_hash =
+        sqlx::query_scalar!("SELECT password_hash FROM users WHERE username = 'alfred'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert!(password_hash.starts_with("$argon2id$"));
+
+    let response = get_stats_as(&app.address, "alfred", "a-long-enough-password").await;
+    assert_eq!(StatusCode::OK, response.status());
+}
+
+#[sqlx::test]
+async fn users_are_listed(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let editor = app.create_user(UserRole::Editor).await;
+
+    let users: serde_json::Value = app
+        .admin_request(Method::GET, "/users")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let users = users.as_array().unwrap();
+    assert_eq!(users.len(), 2);
+    assert!(users
+        .iter()
+        .any(|u| u["username"] == editor.username.as_str() && u["role"] == "EDITOR"));
+}
+
+#[sqlx::test]
+async fn create_user_rejects_invalid_and_duplicate_users(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let test_cases = [
+        (
+            serde_json::json!({ "username": "al fred", "password": "a-long-enough-password", "role": "EDITOR" }),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            serde_json::json!({ "username": "alfred", "password": "short", "role": "EDITOR" }),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            serde_json::json!({ "username": "alfred", "password": "a-long-enough-password", "role": "OWNER" }),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            serde_json::json!({ "username": app.test_user.username, "password": "a-long-enough-password", "role": "EDITOR" }),
+            StatusCode::CONFLICT,
+        ),
+    ];
+
+    for (body, expected_status) in test_cases {
+        let response = app
+            .admin_request(Method::POST, "/users")
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            expected_status,
+            response.status(),
+            "Unexpected status for {}",
+            body
+        );
+    }
+}
+
+#[sqlx::test]
+async fn disabled_user_cannot_authenticate(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let editor = app.create_user(UserRole::Editor).await;
+
+    let response = app
+        .admin_request(Method::POST, &format!("/users/{}/disable", editor.user_id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::OK, response.status());
+    let disabled: serde_json::Value = response.json().await.unwrap();
+    assert!(disabled["disabled_at"].is_string());
+
+    let response = get_stats_as(&app.address, &editor.username, &editor.password).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[sqlx::test]
+async fn deleted_user_cannot_authenticate(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let editor = app.create_user(UserRole::Editor).await;
+
+    let response = app
+        .admin_request(Method::DELETE, &format!("/users/{}", editor.user_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    let response = get_stats_as(&app.address, &editor.username, &editor.password).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+    let response = app
+        .admin_request(Method::DELETE, &format!("/users/{}", editor.user_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+}
+
+#[sqlx::test]
+async fn admins_cannot_disable_or_delete_themselves(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let user_id = app.test_user.user_id;
+
+    for (method, path) in [
+        (Method::POST, format!("/users/{}/disable", user_id)),
+        (Method::DELETE, format!("/users/{}", user_id)),
+    ] {
+        let response = app.admin_request(method, &path).send().await.unwrap();
+
+        assert_eq!(StatusCode::CONFLICT, response.status());
+    }
+}
+
+#[sqlx::test]
+async fn only_admins_can_manage_users(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let user_id = uuid::Uuid::new_v4();
+
+    for role in [UserRole::Editor, UserRole::Viewer] {
+        let user = app.create_user(role).await;
+
+        for (method, path) in [
+            (Method::POST, "/admin/users".to_string()),
+            (Method::GET, "/admin/users".to_string()),
+            (Method::POST, format!("/admin/users/{}/disable", user_id)),
+            (Method::DELETE, format!("/admin/users/{}", user_id)),
+        ] {
+            let response = app
+                .request_as(&user, method.clone(), &path)
+                .json(&serde_json::json!({
+                    "username": "alfred",
+                    "password": "a-long-enough-password",
+                    "role": "ADMIN",
+                }))
+                .send()
+                .await
+                .unwrap();
+
+            assert_eq!(
+                StatusCode::FORBIDDEN,
+                response.status(),
+                "{} {} did not reject a {} user",
+                method,
+                path,
+                role
+            );
+        }
+    }
+}
+
+fn bootstrap_admin() -> BootstrapAdminSettings {
+    BootstrapAdminSettings {
+        username: "root-admin".into(),
+        password: Secret::new("a-long-enough-password".into()),
+    }
+}
+
+#[sqlx::test]
+async fn admin_is_bootstrapped_when_there_is_no_user(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        config.bootstrap_admin = Some(bootstrap_admin());
+    })
+    .await;
+
+    let role = sqlx::query_scalar!(
+        r#"SELECT role AS "role: UserRole" FROM users WHERE username = 'root-admin'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Bootstrap admin was not created");
+    assert_eq!(role, UserRole::Admin);
+
+    let response = get_stats_as(&app.address, "root-admin", "a-long-enough-password").await;
+    assert_eq!(StatusCode::OK, response.status());
+}
+
+#[sqlx::test]
+async fn admin_is_not_bootstrapped_when_users_exist(db_pool: PgPool) {
+    TestUser::generate().store(&db_pool).await;
+
+    let app = spawn_app_with(db_pool, |config| {
+        config.bootstrap_admin = Some(bootstrap_admin());
+    })
+    .await;
+
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM users WHERE username = 'root-admin') AS "exists!""#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(!exists);
+}
@@ -0,0 +1,212 @@
+use reqwest::{Method, StatusCode};
+use sqlx::PgPool;
+
+use crate::helpers::spawn_app;
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn publish_with_token(address: &str, token: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/newsletter", address))
+        .bearer_auth(token)
+        .json(&newsletter_body())
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[sqlx::test]
+async fn created_token_is_returned_once_and_listed_without_its_value(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = app
+        .api_tokens_request(Method::POST, "")
+        .json(&serde_json::json!({
+            "name": "CMS",
+            "scopes": ["newsletter:publish"],
+            "expires_at": "2100-01-01T00:00:00Z",
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::CREATED, response.status());
+    let created: serde_json::Value = response.json().await.unwrap();
+    assert!(created["token"].as_str().unwrap().starts_with("z2p_"));
+    assert_eq!(created["scopes"], serde_json::json!(["newsletter:publish"]));
+
+    let stored_hash = sqlx::query_scalar!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(created["token"].as_str().unwrap(), stored_hash);
+
+    let response = app
+        .api_tokens_request(Method::GET, "")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+
+    let tokens: serde_json::Value = response.json().await.unwrap();
+    let tokens = tokens.as_array().unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0]["id"], created["id"]);
+    assert_eq!(tokens[0]["name"], "CMS");
+    assert_eq!(tokens[0]["expires_at"], "2100-01-01T00:00:00Z");
+    assert!(tokens[0]["last_used_at"].is_null());
+    assert!(tokens[0].get("token").is_none());
+}
+
+#[sqlx::test]
+async fn newsletter_can_be_published_with_a_bearer_token(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let token = app.create_api_token(&["newsletter:publish"]).await;
+
+    let response = publish_with_token(&app.address, &token).await;
+
+    assert_eq!(StatusCode::OK, response.status());
+
+    let last_used_at = sqlx::query_scalar!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(last_used_at.is_some());
+}
+
+#[sqlx::test]
+async fn admin_endpoints_accept_a_bearer_token(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let token = app.create_api_token(&["subscribers:manage"]).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", &app.address))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::OK, response.status());
+}
+
+#[sqlx::test]
+async fn token_without_the_scope_is_forbidden(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let token = app.create_api_token(&["subscribers:manage"]).await;
+
+    let response = publish_with_token(&app.address, &token).await;
+
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+}
+
+#[sqlx::test]
+async fn unknown_expired_and_revoked_tokens_are_rejected(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = publish_with_token(&app.address, "z2p_unknown").await;
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+    let expired = app.create_api_token(&["newsletter:publish"]).await;
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = publish_with_token(&app.address, &expired).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+    let revoked = app.create_api_token(&["newsletter:publish"]).await;
+    let token_id = sqlx::query_scalar!("SELECT id FROM api_tokens WHERE expires_at IS NULL")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app
+        .api_tokens_request(Method::DELETE, &format!("/{}", token_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    let response = publish_with_token(&app.address, &revoked).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[sqlx::test]
+async fn revoking_an_unknown_token_returns_404(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = app
+        .api_tokens_request(Method::DELETE, &format!("/{}", uuid::Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+}
+
+#[sqlx::test]
+async fn api_tokens_cannot_manage_api_tokens(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let token = app
+        .create_api_token(&["newsletter:publish", "subscribers:manage"])
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/api-tokens", &app.address))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "name": "Another", "scopes": ["newsletter:publish"] }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[sqlx::test]
+async fn create_api_token_returns_400_for_invalid_data(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let test_cases = [
+        (
+            serde_json::json!({ "name": " ", "scopes": ["newsletter:publish"] }),
+            "name",
+        ),
+        (serde_json::json!({ "name": "CMS", "scopes": [] }), "scopes"),
+        (
+            serde_json::json!({
+                "name": "CMS",
+                "scopes": ["newsletter:publish"],
+                "expires_at": "2000-01-01T00:00:00Z",
+            }),
+            "expires_at",
+        ),
+    ];
+
+    for (body, field) in test_cases {
+        let response = app
+            .api_tokens_request(Method::POST, "")
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["errors"][0]["field"], field);
+    }
+
+    let response = app
+        .api_tokens_request(Method::POST, "")
+        .json(&serde_json::json!({ "name": "CMS", "scopes": ["everything"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+}
@@ -0,0 +1,314 @@
+use chrono::Utc;
+use reqwest::{Method, StatusCode};
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod_rust::domain::{SubscriptionStatus, UserRole};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn audit_entries(app: &TestApp, query: &str) -> serde_json::Value {
+    let response = app
+        .admin_request(Method::GET, &format!("/audit{}", query))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(StatusCode::OK, response.status());
+
+    response.json().await.unwrap()
+}
+
+#[sqlx::test]
+async fn newsletter_publishes_are_audited_with_a_content_hash(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    app.store_subscriber("bruce@wayne.com", SubscriptionStatus::Confirmed, Utc::now())
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("X-Request-Id", "publish-at-3am")
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Plain text", "html": "<p>Html</p>" },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+
+    let page = audit_entries(&app, "?action=newsletter_published").await;
+    let entries = page["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry["actor_user_id"], app.test_user.user_id.to_string());
+    assert_eq!(entry["request_id"], "publish-at-3am");
+    assert_eq!(entry["details"]["title"], "Newsletter title");
+    assert_eq!(entry["details"]["recipients"], 1);
+    assert_eq!(entry["details"]["content_hash"].as_str().unwrap().len(), 64);
+}
+
+async fn get_stats(app: &TestApp, password: &str) -> StatusCode {
+    reqwest::Client::new()
+        .get(format!("{}/admin/stats", app.address))
+        .basic_auth(&app.test_user.username, Some(password))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[sqlx::test]
+async fn a_login_is_audited_once_per_client_ip_and_interval(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    for _ in 0..2 {
+        assert_eq!(
+            StatusCode::OK,
+            get_stats(&app, &app.test_user.password).await
+        );
+    }
+
+    let page = audit_entries(&app, "?action=login_succeeded").await;
+    let entries = page["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0]["actor_user_id"],
+        app.test_user.user_id.to_string()
+    );
+    assert_eq!(entries[0]["details"]["username"], app.test_user.username);
+    assert_eq!(entries[0]["details"]["client_ip"], "127.0.0.1");
+}
+
+#[sqlx::test]
+async fn every_failed_login_is_audited(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    assert_eq!(
+        StatusCode::UNAUTHORIZED,
+        get_stats(&app, "wrong-password").await
+    );
+
+    let page = audit_entries(&app, "?action=login_failed").await;
+    let entries = page["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0]["actor_user_id"].is_null());
+    assert_eq!(entries[0]["details"]["username"], app.test_user.username);
+}
+
+#[sqlx::test]
+async fn failed_logins_are_audited_when_they_lock_out(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        config.login_throttle.per_username.delay_after = 10;
+        config.login_throttle.per_username.lockout_after = 3;
+    })
+    .await;
+    // The test user is the admin who would read the audit log
+    let lockout_entries = || async {
+        sqlx::query!(
+            r#"SELECT actor_user_id, details FROM audit_log WHERE action = 'login_locked_out'"#
+        )
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+    };
+
+    for _ in 0..2 {
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            get_stats(&app, "wrong-password").await
+        );
+    }
+    assert!(lockout_entries().await.is_empty());
+
+    // Attempts are rejected without being checked once locked out
+    for _ in 0..3 {
+        get_stats(&app, "wrong-password").await;
+    }
+
+    let entries = lockout_entries().await;
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0].actor_user_id.is_none());
+    assert_eq!(entries[0].details["username"], app.test_user.username);
+    assert_eq!(
+        entries[0].details["lockouts"][0]["key"],
+        format!("username:{}", app.test_user.username)
+    );
+}
+
+#[sqlx::test]
+async fn user_and_subscriber_management_is_audited(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let subscriber_id = app
+        .store_subscriber(
+            "bruce@wayne.com",
+            SubscriptionStatus::PendingConfirmation,
+            Utc::now(),
+        )
+        .await;
+
+    let created: serde_json::Value = app
+        .admin_request(Method::POST, "/users")
+        .json(&serde_json::json!({
+            "username": "alfred",
+            "password": "a-long-enough-password",
+            "role": "EDITOR",
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let user_id = created["user_id"].as_str().unwrap();
+    app.admin_request(Method::DELETE, &format!("/users/{}", user_id))
+        .send()
+        .await
+        .unwrap();
+    app.admin_request(
+        Method::POST,
+        &format!("/subscribers/{}/confirm", subscriber_id),
+    )
+    .send()
+    .await
+    .unwrap();
+
+    let page = audit_entries(&app, &format!("?target_id={}", user_id)).await;
+    let actions: Vec<_> = page["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["action"].as_str().unwrap())
+        .collect();
+    // Newest first
+    assert_eq!(actions, vec!["user_deleted", "user_created"]);
+    assert_eq!(page["entries"][0]["details"]["username"], "alfred");
+
+    let page = audit_entries(&app, "?action=subscriber_status_changed").await;
+    let entry = &page["entries"][0];
+    assert_eq!(entry["target_id"], subscriber_id.to_string());
+    assert_eq!(entry["actor_user_id"], app.test_user.user_id.to_string());
+    assert_eq!(entry["details"]["status"], "CONFIRMED");
+}
+
+#[sqlx::test]
+async fn user_management_is_undone_when_it_cannot_be_audited(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let user = app.create_user(UserRole::Editor).await;
+
+    // Sabotage the audit log for user management only
+    sqlx::query!(
+        "ALTER TABLE audit_log ADD CONSTRAINT no_user_events CHECK (action NOT LIKE 'user_%')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    for (method, path) in [
+        (Method::POST, format!("/users/{}/disable", user.user_id)),
+        (Method::DELETE, format!("/users/{}", user.user_id)),
+    ] {
+        let response = app.admin_request(method, &path).send().await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+    }
+
+    let disabled_at = sqlx::query_scalar!(
+        "SELECT disabled_at FROM users WHERE user_id = $1",
+        user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("User was deleted");
+    assert!(disabled_at.is_none());
+}
+
+#[sqlx::test]
+async fn audit_log_is_paginated(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    for username in ["alfred", "barbara", "dick"] {
+        let response = app
+            .admin_request(Method::POST, "/users")
+            .json(&serde_json::json!({
+                "username": username,
+                "password": "a-long-enough-password",
+                "role": "EDITOR",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+    }
+
+    let first = audit_entries(&app, "?action=user_created&limit=2").await;
+    assert_eq!(first["entries"].as_array().unwrap().len(), 2);
+    let cursor = first["next_cursor"].as_str().unwrap();
+
+    let second = audit_entries(
+        &app,
+        &format!("?action=user_created&limit=2&cursor={}", cursor),
+    )
+    .await;
+    assert_eq!(second["entries"].as_array().unwrap().len(), 1);
+    assert!(second["next_cursor"].is_null());
+    assert!(second["entries"][0]["id"].as_i64() < first["entries"][1]["id"].as_i64());
+}
+
+#[sqlx::test]
+async fn invalid_audit_filters_are_rejected(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    for query in ["?action=made_coffee", "?limit=0", "?cursor=not-a-cursor"] {
+        let response = app
+            .admin_request(Method::GET, &format!("/audit{}", query))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            response.status(),
+            "Unexpected status for {}",
+            query
+        );
+    }
+}
+
+#[sqlx::test]
+async fn only_admins_can_read_the_audit_log(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let editor = app.create_user(UserRole::Editor).await;
+
+    let response = app
+        .request_as(&editor, Method::GET, "/admin/audit")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+}
+
+#[sqlx::test]
+async fn audit_log_entries_cannot_be_changed(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    sqlx::query("INSERT INTO audit_log (occurred_at, action) VALUES (now(), 'user_created')")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let update = sqlx::query("UPDATE audit_log SET action = 'nothing_happened'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query("DELETE FROM audit_log")
+        .execute(&app.db_pool)
+        .await;
+
+    assert!(update.is_err());
+    assert!(delete.is_err());
+}
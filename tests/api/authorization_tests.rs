@@ -0,0 +1,196 @@
+use chrono::Utc;
+use reqwest::{Method, StatusCode};
+use sqlx::PgPool;
+use zero2prod_rust::domain::{SubscriptionStatus, UserRole};
+
+use crate::helpers::{spawn_app, TestApp, TestUser};
+
+fn newsletter_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+/// Every subscriber management route, with a body valid for all of them.
+fn subscriber_routes() -> Vec<(Method, String)> {
+    let subscriber_id = uuid::Uuid::new_v4();
+
+    vec![
+        (Method::GET, "/admin/subscribers".to_string()),
+        (Method::GET, format!("/admin/subscribers/{}", subscriber_id)),
+        (
+            Method::DELETE,
+            format!("/admin/subscribers/{}", subscriber_id),
+        ),
+        (
+            Method::POST,
+            format!("/admin/subscribers/{}/confirm", subscriber_id),
+        ),
+        (
+            Method::PUT,
+            format!("/admin/subscribers/{}/status", subscriber_id),
+        ),
+    ]
+}
+
+async fn send_as(
+    app: &TestApp,
+    user: &TestUser,
+    method: Method,
+    path: &str,
+    body: &serde_json::Value,
+) -> reqwest::Response {
+    app.request_as(user, method, path)
+        .json(body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+#[sqlx::test]
+async fn only_admins_can_manage_subscribers(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let body = serde_json::json!({ "status": "CONFIRMED" });
+
+    for role in [UserRole::Editor, UserRole::Viewer] {
+        let user = app.create_user(role).await;
+
+        for (method, path) in subscriber_routes() {
+            let response = send_as(&app, &user, method.clone(), &path, &body).await;
+
+            assert_eq!(
+                StatusCode::FORBIDDEN,
+                response.status(),
+                "{} {} did not reject a {} user",
+                method,
+                path,
+                role
+            );
+            assert_eq!(
+                "application/problem+json",
+                response.headers()["Content-Type"]
+            );
+        }
+    }
+
+    let admin = app.create_user(UserRole::Admin).await;
+    for (method, path) in subscriber_routes() {
+        let response = send_as(&app, &admin, method.clone(), &path, &body).await;
+
+        assert_ne!(
+            StatusCode::FORBIDDEN,
+            response.status(),
+            "{} {} rejected an admin",
+            method,
+            path
+        );
+    }
+}
+
+#[sqlx::test]
+async fn only_editors_and_admins_can_publish(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let viewer = app.create_user(UserRole::Viewer).await;
+    let response = send_as(
+        &app,
+        &viewer,
+        Method::POST,
+        "/newsletter",
+        &newsletter_body(),
+    )
+    .await;
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+
+    for role in [UserRole::Editor, UserRole::Admin] {
+        let user = app.create_user(role).await;
+        let response = send_as(&app, &user, Method::POST, "/newsletter", &newsletter_body()).await;
+
+        assert_eq!(
+            StatusCode::OK,
+            response.status(),
+            "{} could not publish",
+            role
+        );
+    }
+}
+
+#[sqlx::test]
+async fn api_token_cannot_exceed_the_role_of_its_owner(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let viewer = app.create_user(UserRole::Viewer).await;
+
+    let response = app
+        .request_as(&viewer, Method::POST, "/api-tokens")
+        .json(&serde_json::json!({ "name": "CMS", "scopes": ["newsletter:publish"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::CREATED, response.status());
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", app.address))
+        .bearer_auth(body["token"].as_str().unwrap())
+        .json(&newsletter_body())
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+}
+
+#[sqlx::test]
+async fn every_role_can_view_stats(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let now = Utc::now();
+
+    app.store_subscriber("a@example.com", SubscriptionStatus::Confirmed, now)
+        .await;
+    app.store_subscriber("b@example.com", SubscriptionStatus::Confirmed, now)
+        .await;
+    app.store_subscriber("c@example.com", SubscriptionStatus::Unsubscribed, now)
+        .await;
+
+    for role in [UserRole::Viewer, UserRole::Editor, UserRole::Admin] {
+        let user = app.create_user(role).await;
+        let response = app
+            .request_as(&user, Method::GET, "/admin/stats")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            StatusCode::OK,
+            response.status(),
+            "{} cannot view stats",
+            role
+        );
+
+        let stats: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            stats["subscribers"],
+            serde_json::json!({
+                "total": 3,
+                "pending_confirmation": 0,
+                "confirmed": 2,
+                "unsubscribed": 1,
+            })
+        );
+    }
+}
+
+#[sqlx::test]
+async fn stats_reject_unauthenticated_requests(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = reqwest::get(format!("{}/admin/stats", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
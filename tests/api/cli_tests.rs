@@ -0,0 +1,249 @@
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod_rust::{
+    authentication::{LoginThrottle, PasswordHashing},
+    cli::{
+        create_user, import_subscribers, list_subscribers, reset_password, run_admin_command,
+        AdminCommand, AdminContext,
+    },
+    configuration::get_configuration,
+    domain::{SubscriptionStatus, UserRole},
+    email_client::EmailClient,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+struct Tools {
+    email_client: EmailClient,
+    password_hashing: PasswordHashing,
+    login_throttle: LoginThrottle,
+}
+
+impl Tools {
+    fn new(app: &TestApp) -> Self {
+        let config = get_configuration().unwrap();
+        Self {
+            email_client: EmailClient::new(
+                &app.email_server.uri(),
+                config.email.sender(),
+                config.email.authorization_token.clone(),
+                config.email.timeout(),
+            ),
+            password_hashing: PasswordHashing::new(&config.password_hashing).unwrap(),
+            login_throttle: LoginThrottle::new(&config.login_throttle),
+        }
+    }
+
+    async fn run(&self, app: &TestApp, command: AdminCommand) -> Result<String, anyhow::Error> {
+        let mut out = vec![];
+        let context = AdminContext {
+            db_pool: &app.db_pool,
+            email_client: &self.email_client,
+            password_hashing: &self.password_hashing,
+            login_throttle: &self.login_throttle,
+        };
+        run_admin_command(command, context, &mut out).await?;
+
+        Ok(String::from_utf8(out).unwrap())
+    }
+}
+
+async fn login(app: &TestApp, username: &str, password: &str) -> reqwest::StatusCode {
+    reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", app.address))
+        .basic_auth(username, Some(password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .status()
+}
+
+#[sqlx::test]
+async fn created_user_can_log_in_with_the_generated_password(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let tools = Tools::new(&app);
+
+    let output = tools
+        .run(
+            &app,
+            AdminCommand::CreateUser {
+                username: "alfred".into(),
+                role: UserRole::Admin,
+                email: None,
+                password_stdin: false,
+            },
+        )
+        .await
+        .unwrap();
+
+    let password = output
+        .lines()
+        .find_map(|line| line.strip_prefix("Password: "))
+        .expect("No password printed");
+    assert_eq!(
+        login(&app, "alfred", password).await,
+        reqwest::StatusCode::OK
+    );
+}
+
+#[sqlx::test]
+async fn invalid_or_taken_usernames_are_rejected(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let tools = Tools::new(&app);
+    let password = || Secret::new("a-long-enough-password".to_string());
+
+    for username in ["two words".to_string(), app.test_user.username.clone()] {
+        let outcome = create_user(
+            username,
+            None,
+            password(),
+            UserRole::Editor,
+            &tools.password_hashing,
+            &app.db_pool,
+        )
+        .await;
+        assert!(outcome.is_err());
+    }
+}
+
+#[sqlx::test]
+async fn reset_password_replaces_the_old_one(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let tools = Tools::new(&app);
+    let new_password = "a-brand-new-password";
+
+    reset_password(
+        &app.test_user.username,
+        Secret::new(new_password.into()),
+        &tools.password_hashing,
+        &tools.login_throttle,
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+
+    let username = &app.test_user.username;
+    assert_eq!(
+        login(&app, username, &app.test_user.password).await,
+        reqwest::StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        login(&app, username, new_password).await,
+        reqwest::StatusCode::OK
+    );
+}
+
+#[sqlx::test]
+async fn reset_password_of_an_unknown_user_fails(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let tools = Tools::new(&app);
+
+    let outcome = reset_password(
+        "nobody",
+        Secret::new("a-brand-new-password".into()),
+        &tools.password_hashing,
+        &tools.login_throttle,
+        &app.db_pool,
+    )
+    .await;
+
+    assert!(outcome.is_err());
+}
+
+#[sqlx::test]
+async fn import_adds_valid_rows_and_reports_the_others(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let csv = "email,name\n\
+        bruce@wayne.com,Bruce Wayne\n\
+        not-an-email,Nobody\n\
+        alfred@wayne.com , Alfred \n\
+        BRUCE@wayne.com,Bruce again\n\
+        only-one-column\n";
+
+    let report = import_subscribers(csv.as_bytes(), SubscriptionStatus::Confirmed, &app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(report.imported, 2);
+    assert_eq!(report.already_subscribed, 1);
+    let invalid_lines: Vec<u64> = report.invalid.iter().map(|row| row.line).collect();
+    assert_eq!(invalid_lines, vec![3, 6]);
+
+    let mut out = vec![];
+    list_subscribers(Some(SubscriptionStatus::Confirmed), &app.db_pool, &mut out)
+        .await
+        .unwrap();
+    let listed = String::from_utf8(out).unwrap();
+    let mut lines = listed.lines();
+    assert_eq!(lines.next(), Some("email,name,status,subscribed_at"));
+    let emails: Vec<&str> = lines.map(|l| l.split(',').next().unwrap()).collect();
+    assert_eq!(emails, vec!["bruce@wayne.com", "alfred@wayne.com"]);
+
+    let audited = sqlx::query_scalar!(
+        r#"SELECT details FROM audit_log WHERE action = 'subscribers_imported'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(audited["imported"], 2);
+}
+
+#[sqlx::test]
+async fn test_email_is_sent_to_the_given_address(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let tools = Tools::new(&app);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let output = tools
+        .run(
+            &app,
+            AdminCommand::SendTestEmail {
+                address: "ops@example.com".into(),
+            },
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(output, "Test email sent\n");
+    let request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = request.body_json().unwrap();
+    assert_eq!(body["To"], "ops@example.com");
+}
+
+#[sqlx::test]
+async fn import_skips_an_address_subscribed_concurrently(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    // A signup inserts the address without committing yet
+    let mut signup = app.db_pool.begin().await.unwrap();
+    sqlx::query!(
+        r#"INSERT INTO subscriptions (id, email, display_email, name, subscribed_at, status)
+        VALUES ($1, 'bruce@wayne.com', 'bruce@wayne.com', 'Bruce', now(), 'PENDING_CONFIRMATION')"#,
+        Uuid::new_v4()
+    )
+    .execute(&mut *signup)
+    .await
+    .unwrap();
+
+    let db_pool = app.db_pool.clone();
+    let import = tokio::spawn(async move {
+        let csv = "email,name\nbruce@wayne.com,Bruce Wayne\n";
+        import_subscribers(csv.as_bytes(), SubscriptionStatus::Confirmed, &db_pool).await
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    signup.commit().await.unwrap();
+
+    let report = import.await.unwrap().unwrap();
+    assert_eq!(report.imported, 0);
+    assert_eq!(report.already_subscribed, 1);
+}
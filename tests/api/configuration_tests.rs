@@ -0,0 +1,40 @@
+use std::process::Command;
+
+fn config_check(env: &[(&str, &str)]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_zero2prod-rust"))
+        .args(["config", "check"])
+        .envs(env.iter().copied())
+        .output()
+        .expect("Failed to run config check")
+}
+
+#[test]
+fn config_check_prints_the_redacted_configuration() {
+    let output = config_check(&[("APP_EMAIL__AUTHORIZATION_TOKEN", "my-secret-token")]);
+
+    assert!(output.status.success());
+    let printed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(printed["email"]["authorization_token"], "[REDACTED]");
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("my-secret-token"));
+}
+
+#[test]
+fn config_check_reports_every_invalid_setting() {
+    let output = config_check(&[
+        ("APP_EMAIL__SENDER", "not-an-email"),
+        ("APP_APPLICATION__BASE_URL", "localhost"),
+    ]);
+
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.contains("email.sender:"), "{}", errors);
+    assert!(errors.contains("application.base_url:"), "{}", errors);
+}
+
+#[test]
+fn unknown_environment_is_reported() {
+    let output = config_check(&[("APP_ENV", "staging")]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("APP_ENV: staging"));
+}
@@ -1,6 +1,9 @@
+use std::time::Duration;
+
 use sqlx::PgPool;
+use wiremock::{matchers::method, Mock, ResponseTemplate};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
 
 #[sqlx::test]
 async fn health_check_works(db_pool: PgPool) {
@@ -17,3 +20,140 @@ async fn health_check_works(db_pool: PgPool) {
     assert!(response.status().is_success());
     assert_eq!(Some(5), response.content_length());
 }
+
+async fn get_readiness(app: &TestApp) -> (reqwest::StatusCode, serde_json::Value) {
+    let response = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    (response.status(), response.json().await.unwrap())
+}
+
+#[sqlx::test]
+async fn liveness_does_not_depend_on_the_database(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    app.db_pool.close().await;
+
+    let response = reqwest::get(format!("{}/health/live", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+}
+
+#[sqlx::test]
+async fn ready_when_every_component_is_ok(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let (status, body) = get_readiness(&app).await;
+
+    assert_eq!(reqwest::StatusCode::OK, status);
+    assert_eq!(body["ready"], true);
+    for component in ["database", "migrations", "email_provider"] {
+        assert_eq!(
+            body["components"][component]["status"], "ok",
+            "{}",
+            component
+        );
+    }
+}
+
+#[sqlx::test]
+async fn not_ready_when_the_database_is_down(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    app.db_pool.close().await;
+
+    let (status, body) = get_readiness(&app).await;
+
+    assert_eq!(reqwest::StatusCode::SERVICE_UNAVAILABLE, status);
+    assert_eq!(body["ready"], false);
+    assert_eq!(
+        body["components"]["database"],
+        serde_json::json!({ "status": "failing" })
+    );
+}
+
+#[sqlx::test]
+async fn not_ready_with_pending_migrations(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    sqlx::query(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT max(version) FROM _sqlx_migrations)",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let (status, body) = get_readiness(&app).await;
+
+    assert_eq!(reqwest::StatusCode::SERVICE_UNAVAILABLE, status);
+    // Which migrations are pending is only logged
+    assert_eq!(
+        body["components"]["migrations"],
+        serde_json::json!({ "status": "failing" })
+    );
+    assert_eq!(body["components"]["database"]["status"], "ok");
+}
+
+#[sqlx::test]
+async fn not_ready_when_the_email_provider_is_unreachable(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        // Nothing listens on the discard port
+        config.email.base_url = "http://127.0.0.1:9".into();
+    })
+    .await;
+
+    let (status, body) = get_readiness(&app).await;
+
+    assert_eq!(reqwest::StatusCode::SERVICE_UNAVAILABLE, status);
+    assert_eq!(body["components"]["email_provider"]["status"], "failing");
+}
+
+#[sqlx::test]
+async fn email_provider_check_can_be_skipped(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        config.email.base_url = "http://127.0.0.1:9".into();
+        config.health.check_email_provider = false;
+    })
+    .await;
+
+    let (status, body) = get_readiness(&app).await;
+
+    assert_eq!(reqwest::StatusCode::OK, status);
+    assert_eq!(body["components"]["email_provider"]["status"], "skipped");
+}
+
+#[sqlx::test]
+async fn slow_checks_time_out(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        config.health.timeout_milliseconds = 200;
+    })
+    .await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .mount(&app.email_server)
+        .await;
+
+    let (status, body) = get_readiness(&app).await;
+
+    assert_eq!(reqwest::StatusCode::SERVICE_UNAVAILABLE, status);
+    assert_eq!(body["components"]["email_provider"]["status"], "failing");
+}
+
+#[sqlx::test]
+async fn readiness_is_cached(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        config.health.cache_seconds = 60;
+    })
+    .await;
+
+    let (status, _) = get_readiness(&app).await;
+    assert_eq!(reqwest::StatusCode::OK, status);
+
+    sqlx::query("DELETE FROM _sqlx_migrations")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let (status, _) = get_readiness(&app).await;
+    assert_eq!(reqwest::StatusCode::OK, status);
+}
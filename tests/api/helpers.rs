@@ -1,9 +1,11 @@
 use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use sqlx::PgPool;
+use tokio::{sync::oneshot, task::JoinHandle};
 use wiremock::MockServer;
 use zero2prod_rust::{
-    configuration::get_configuration,
+    configuration::{get_configuration, LogFormat, LoggingSettings, Settings},
+    domain::{SubscriptionStatus, UserRole},
     startup::Application,
     telemetry::{get_subscriber, init_subscriber},
 };
@@ -11,9 +13,12 @@ use zero2prod_rust::{
 pub struct TestApp {
     pub address: String,
     pub port: u16,
+    pub metrics_port: Option<u16>,
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub test_user: TestUser,
+    shutdown: Option<oneshot::Sender<()>>,
+    server: Option<JoinHandle<Result<(), std::io::Error>>>,
 }
 
 pub struct ConfirmationLinks {
@@ -22,6 +27,25 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    /// Sends the app the equivalent of SIGTERM, without waiting for it to
+    /// shut down.
+    pub fn trigger_shutdown(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+
+    /// Shuts the app down and waits until it has.
+    pub async fn shutdown(&mut self) {
+        self.trigger_shutdown();
+        if let Some(server) = self.server.take() {
+            server
+                .await
+                .expect("Server task panicked")
+                .expect("Server failed");
+        }
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         let client = reqwest::Client::new();
         client
@@ -33,6 +57,15 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_subscriptions_json(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -71,20 +104,97 @@ impl TestApp {
             .await
             .expect("Failed to execute request")
     }
+
+    pub fn api_tokens_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+    ) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}/api-tokens{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
+    /// Creates an API token for the test user and returns its value.
+    pub async fn create_api_token(&self, scopes: &[&str]) -> String {
+        let response = self
+            .api_tokens_request(reqwest::Method::POST, "")
+            .json(&serde_json::json!({ "name": "CMS", "scopes": scopes }))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(reqwest::StatusCode::CREATED, response.status());
+
+        let body: serde_json::Value = response.json().await.unwrap();
+        body["token"].as_str().unwrap().to_string()
+    }
+
+    /// Stores a new user with `role`, in addition to the test user.
+    pub async fn create_user(&self, role: UserRole) -> TestUser {
+        let user = TestUser::with_role(role);
+        user.store(&self.db_pool).await;
+        user
+    }
+
+    pub fn request_as(
+        &self,
+        user: &TestUser,
+        method: reqwest::Method,
+        path: &str,
+    ) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}{}", &self.address, path))
+            .basic_auth(&user.username, Some(&user.password))
+    }
+
+    pub fn admin_request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        reqwest::Client::new()
+            .request(method, format!("{}/admin{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
+    pub async fn store_subscriber(
+        &self,
+        email: &str,
+        status: SubscriptionStatus,
+        subscribed_at: chrono::DateTime<chrono::Utc>,
+    ) -> uuid::Uuid {
+        let subscriber_id = uuid::Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO subscriptions (id, email, display_email, name, subscribed_at, status) VALUES ($1, $2, $3, $4, $5, $6)",
+            subscriber_id,
+            email,
+            email,
+            "Test Subscriber",
+            subscribed_at,
+            status as SubscriptionStatus,
+        )
+        .execute(&self.db_pool)
+        .await
+        .expect("Failed to store subscriber");
+
+        subscriber_id
+    }
 }
 
 pub struct TestUser {
     pub user_id: uuid::Uuid,
     pub username: String,
     pub password: String,
+    pub role: UserRole,
 }
 
 impl TestUser {
     pub fn generate() -> Self {
+        Self::with_role(UserRole::Admin)
+    }
+
+    pub fn with_role(role: UserRole) -> Self {
         Self {
             user_id: uuid::Uuid::new_v4(),
             username: uuid::Uuid::new_v4().to_string(),
             password: uuid::Uuid::new_v4().to_string(),
+            role,
         }
     }
 
@@ -101,10 +211,11 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash) values($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, role) values($1, $2, $3, $4)",
             self.user_id,
             self.username,
-            password_hash
+            password_hash,
+            self.role as UserRole
         )
         .execute(db_pool)
         .await
@@ -125,17 +236,28 @@ static TRACING: Lazy<()> = Lazy::new(|| {
         log_filter_level = std::env::var("LOG_LEVEL").unwrap();
     }
 
+    let logging = LoggingSettings {
+        format: LogFormat::Bunyan,
+        level: log_filter_level,
+        modules: Default::default(),
+        file: None,
+    };
+
     if std::env::var("TEST_LOG").unwrap_or("false".into()) == "true" {
-        let subscriber = get_subscriber(subscriber_name, log_filter_level, std::io::stdout);
+        let subscriber = get_subscriber(subscriber_name, &logging, std::io::stdout, None).unwrap();
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(subscriber_name, log_filter_level, std::io::sink);
+        let subscriber = get_subscriber(subscriber_name, &logging, std::io::sink, None).unwrap();
         init_subscriber(subscriber);
     }
 });
 
-#[allow(clippy::let_underscore_future)]
 pub async fn spawn_app(db_pool: PgPool) -> TestApp {
+    spawn_app_with(db_pool, |_| {}).await
+}
+
+/// Spawns the app after applying test specific overrides to its config.
+pub async fn spawn_app_with(db_pool: PgPool, configure: impl FnOnce(&mut Settings)) -> TestApp {
     // This will be called only once
     Lazy::force(&TRACING);
 
@@ -145,21 +267,32 @@ pub async fn spawn_app(db_pool: PgPool) -> TestApp {
     // override config for test
     config.application.port = 0; // for selecting random port
     config.email.base_url = email_server.uri();
+    configure(&mut config);
 
     let app: Application = Application::build(config, db_pool.clone())
         .await
         .expect("Failed to start server");
     let address = format!("http://127.0.0.1:{}", app.port);
     let port = app.port;
+    let metrics_port = app.metrics_port;
 
-    let _ = tokio::spawn(app.run_until_stopped());
+    let (shutdown, shutdown_rx) = oneshot::channel();
+    // A dropped sender means the test is over, not that it asked to stop
+    let server = tokio::spawn(app.run_until(async move {
+        if shutdown_rx.await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }));
 
     let test_app = TestApp {
         address,
         port,
+        metrics_port,
         db_pool,
         email_server,
         test_user: TestUser::generate(),
+        shutdown: Some(shutdown),
+        server: Some(server),
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -0,0 +1,154 @@
+use std::time::{Duration, Instant};
+
+use sqlx::PgPool;
+use zero2prod_rust::configuration::{FailureThresholds, Settings};
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+const NO_LIMIT: FailureThresholds = FailureThresholds {
+    delay_after: 1000,
+    lockout_after: 1000,
+};
+
+fn throttle_usernames(config: &mut Settings) {
+    config.login_throttle.per_username = FailureThresholds {
+        delay_after: 1000,
+        lockout_after: 3,
+    };
+    config.login_throttle.per_ip = NO_LIMIT;
+}
+
+async fn list_subscribers_as(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", &app.address))
+        .basic_auth(username, Some(password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn fail_logins(app: &TestApp, username: &str, count: usize) {
+    for _ in 0..count {
+        let response = list_subscribers_as(app, username, "wrong-password").await;
+        assert_eq!(reqwest::StatusCode::UNAUTHORIZED, response.status());
+    }
+}
+
+#[sqlx::test]
+async fn username_is_locked_out_after_repeated_failures(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, throttle_usernames).await;
+    let username = app.test_user.username.clone();
+
+    fail_logins(&app, &username, 3).await;
+
+    // Even the right password is rejected while locked out
+    let response = list_subscribers_as(&app, &username, &app.test_user.password).await;
+
+    assert_eq!(reqwest::StatusCode::TOO_MANY_REQUESTS, response.status());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+
+    let locked_until = sqlx::query_scalar!(
+        "SELECT locked_until FROM login_failures WHERE key = $1",
+        format!("username:{}", username)
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch login failures");
+    assert!(locked_until.is_some());
+}
+
+#[sqlx::test]
+async fn client_ip_is_locked_out_across_usernames(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        config.login_throttle.per_username = NO_LIMIT;
+        config.login_throttle.per_ip = FailureThresholds {
+            delay_after: 1000,
+            lockout_after: 3,
+        };
+    })
+    .await;
+
+    for _ in 0..3 {
+        fail_logins(&app, &uuid::Uuid::new_v4().to_string(), 1).await;
+    }
+
+    let response =
+        list_subscribers_as(&app, &app.test_user.username, &app.test_user.password).await;
+
+    assert_eq!(reqwest::StatusCode::TOO_MANY_REQUESTS, response.status());
+}
+
+#[sqlx::test]
+async fn successful_login_resets_username_failures(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, throttle_usernames).await;
+    let username = app.test_user.username.clone();
+
+    fail_logins(&app, &username, 2).await;
+    let response = list_subscribers_as(&app, &username, &app.test_user.password).await;
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+
+    fail_logins(&app, &username, 2).await;
+    let response = list_subscribers_as(&app, &username, &app.test_user.password).await;
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+}
+
+#[sqlx::test]
+async fn attempts_are_delayed_after_repeated_failures(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        config.login_throttle.base_delay_milliseconds = 500;
+        config.login_throttle.per_username = FailureThresholds {
+            delay_after: 1,
+            lockout_after: 1000,
+        };
+        config.login_throttle.per_ip = NO_LIMIT;
+    })
+    .await;
+
+    fail_logins(&app, &app.test_user.username, 1).await;
+
+    let start = Instant::now();
+    fail_logins(&app, &app.test_user.username, 1).await;
+
+    assert!(start.elapsed() >= Duration::from_millis(500));
+}
+
+/// Locks out the test user, waits for the lockout to end and fails once
+/// more, which must not lock it out again.
+async fn assert_failures_restart_after_lockout(
+    db_pool: PgPool,
+    failure_window_seconds: u64,
+    lockout_seconds: u64,
+) {
+    let app = spawn_app_with(db_pool, |config| {
+        throttle_usernames(config);
+        config.login_throttle.failure_window_seconds = failure_window_seconds;
+        config.login_throttle.lockout_seconds = lockout_seconds;
+    })
+    .await;
+    let username = app.test_user.username.clone();
+
+    fail_logins(&app, &username, 3).await;
+    let response = list_subscribers_as(&app, &username, &app.test_user.password).await;
+    assert_eq!(reqwest::StatusCode::TOO_MANY_REQUESTS, response.status());
+
+    tokio::time::sleep(Duration::from_millis(lockout_seconds * 1000 + 100)).await;
+    fail_logins(&app, &username, 1).await;
+
+    let response = list_subscribers_as(&app, &username, &app.test_user.password).await;
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+}
+
+#[sqlx::test]
+async fn failures_restart_after_a_lockout_shorter_than_the_window(db_pool: PgPool) {
+    assert_failures_restart_after_lockout(db_pool, 60, 1).await;
+}
+
+#[sqlx::test]
+async fn failures_restart_after_a_lockout_longer_than_the_window(db_pool: PgPool) {
+    assert_failures_restart_after_lockout(db_pool, 1, 2).await;
+}
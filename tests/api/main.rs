@@ -1,5 +1,22 @@
+mod admin_subscribers_tests;
+mod admin_users_tests;
+mod api_tokens_tests;
+mod audit_tests;
+mod authorization_tests;
+mod cli_tests;
+mod configuration_tests;
 mod health_check_tests;
 mod helpers;
+mod login_throttle_tests;
+mod metrics_tests;
+mod migrations_tests;
 mod newsletter_tests;
+mod password_reset_tests;
+mod password_upgrade_tests;
+mod problem_details_tests;
+mod rate_limit_tests;
+mod request_id_tests;
+mod shutdown_tests;
 mod subscriptions_confirm_tests;
 mod subscriptions_tests;
+mod two_factor_tests;
@@ -0,0 +1,143 @@
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use zero2prod_rust::domain::UserRole;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp, TestUser};
+
+async fn scrape(request: reqwest::RequestBuilder) -> String {
+    let response = request.send().await.expect("Failed to execute request");
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+
+    response.text().await.unwrap()
+}
+
+/// Scrapes the application listener as a viewer.
+async fn scrape_app(app: &TestApp) -> String {
+    let user = TestUser::with_role(UserRole::Viewer);
+    user.store(&app.db_pool).await;
+
+    scrape(
+        reqwest::Client::new()
+            .get(format!("{}/metrics", app.address))
+            .basic_auth(&user.username, Some(&user.password)),
+    )
+    .await
+}
+
+async fn subscribe(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn requests_are_counted_per_route_pattern(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let subscriber_id = uuid::Uuid::new_v4();
+
+    reqwest::Client::new()
+        .get(format!(
+            "{}/admin/subscribers/{}",
+            app.address, subscriber_id
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    reqwest::get(format!("{}/no-such-route", app.address))
+        .await
+        .unwrap();
+
+    let metrics = scrape_app(&app).await;
+
+    assert!(metrics.contains(
+        r#"zero2prod_http_requests_total{method="GET",route="/admin/subscribers/{subscriber_id}",status="404"} 1"#
+    ));
+    assert!(metrics.contains(
+        r#"zero2prod_http_requests_total{method="GET",route="unmatched",status="404"} 1"#
+    ));
+    assert!(!metrics.contains(&subscriber_id.to_string()));
+    assert!(metrics.contains("zero2prod_http_request_duration_seconds_bucket"));
+    assert!(metrics.contains("zero2prod_db_pool_max_connections"));
+}
+
+#[sqlx::test]
+async fn signups_and_emails_are_counted(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    subscribe(&app).await;
+
+    let metrics = scrape_app(&app).await;
+    assert!(metrics.contains("zero2prod_subscriptions_created_total 1"));
+    assert!(metrics.contains(r#"zero2prod_emails_total{outcome="sent"} 1"#));
+}
+
+#[sqlx::test]
+async fn following_a_confirmation_link_twice_counts_one_confirmation(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    subscribe(&app).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    for _ in 0..2 {
+        let response = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    let metrics = scrape_app(&app).await;
+    assert!(metrics.contains("zero2prod_subscriptions_confirmed_total 1"));
+}
+
+#[sqlx::test]
+async fn metrics_can_be_served_on_a_separate_port(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| config.metrics.port = Some(0)).await;
+    let metrics_port = app.metrics_port.expect("No metrics port");
+
+    let response = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .unwrap();
+    assert_eq!(404, response.status().as_u16());
+
+    let metrics =
+        scrape(reqwest::Client::new().get(format!("http://127.0.0.1:{}/metrics", metrics_port)))
+            .await;
+    assert!(metrics.contains("zero2prod_db_pool_connections"));
+}
+
+#[sqlx::test]
+async fn metrics_on_the_application_port_require_authentication(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn metrics_are_not_served_when_disabled(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| config.metrics.enabled = false).await;
+
+    let response = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(404, response.status().as_u16());
+}
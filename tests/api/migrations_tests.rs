@@ -0,0 +1,155 @@
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+use zero2prod_rust::{
+    health::applied_migrations,
+    startup::{run_migrations, MIGRATOR},
+};
+
+use crate::helpers::spawn_app_with;
+
+#[sqlx::test(migrations = false)]
+async fn migrations_are_applied_on_startup_when_enabled(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |c| {
+        c.database.run_migrations_on_startup = true;
+        c.health.check_email_provider = false;
+    })
+    .await;
+
+    let response = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
+
+#[sqlx::test(migrations = false)]
+async fn concurrent_migration_runs_do_not_race(db_pool: PgPool) {
+    let (first, second) = tokio::join!(run_migrations(&db_pool), run_migrations(&db_pool));
+
+    first.expect("First run failed");
+    second.expect("Second run failed");
+    assert_eq!(
+        applied_migrations(&db_pool).await.unwrap().len(),
+        MIGRATOR.iter().count()
+    );
+}
+
+#[sqlx::test(migrations = false)]
+async fn applied_migrations_are_audited(db_pool: PgPool) {
+    run_migrations(&db_pool).await.expect("Failed to migrate");
+    run_migrations(&db_pool).await.expect("Failed to migrate");
+
+    let details: Vec<serde_json::Value> =
+        sqlx::query_scalar("SELECT details FROM audit_log WHERE action = 'migrations_applied'")
+            .fetch_all(&db_pool)
+            .await
+            .unwrap();
+    assert_eq!(details.len(), 1);
+    assert_eq!(
+        details[0]["versions"].as_array().unwrap().len(),
+        MIGRATOR.iter().count()
+    );
+}
+
+#[sqlx::test]
+async fn migrating_an_up_to_date_database_is_a_no_op(db_pool: PgPool) {
+    run_migrations(&db_pool).await.expect("Failed to migrate");
+
+    assert_eq!(
+        applied_migrations(&db_pool).await.unwrap().len(),
+        MIGRATOR.iter().count()
+    );
+}
+
+/// Runs the SQL of the embedded migrations in `versions`, bypassing the
+/// migrator, to set up the schema as it was at some point.
+async fn apply_migrations(db_pool: &PgPool, versions: impl Fn(i64) -> bool) {
+    for migration in MIGRATOR.iter().filter(|m| versions(m.version)) {
+        db_pool
+            .execute(migration.sql.as_ref())
+            .await
+            .unwrap_or_else(|e| panic!("Migration {} failed: {}", migration.version, e));
+    }
+}
+
+#[sqlx::test(migrations = false)]
+async fn emails_differing_by_case_are_merged_when_normalizing(db_pool: PgPool) {
+    const NORMALIZE_EMAILS: i64 = 20261018110000;
+    apply_migrations(&db_pool, |version| version < NORMALIZE_EMAILS).await;
+
+    let kept = Uuid::new_v4();
+    let duplicate = Uuid::new_v4();
+    sqlx::query(
+        r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES
+        ($1, 'Bob@Example.COM', 'Bob', now(), 'CONFIRMED'),
+        ($2, 'bob@example.com', 'Bob', now() - interval '1 day', 'PENDING_CONFIRMATION'),
+        ($3, 'bob@EXAMPLE.com', 'Bob', now(), 'PENDING_CONFIRMATION'),
+        ($4, 'alice@example.com', 'Alice', now(), 'CONFIRMED')"#,
+    )
+    .bind(kept)
+    .bind(duplicate)
+    .bind(Uuid::new_v4())
+    .bind(Uuid::new_v4())
+    .execute(&db_pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO subscription_tokens (subscription_token, subscriber_id) VALUES ('token', $1)",
+    )
+    .bind(duplicate)
+    .execute(&db_pool)
+    .await
+    .unwrap();
+
+    apply_migrations(&db_pool, |version| version == NORMALIZE_EMAILS).await;
+
+    let remaining: Vec<(Uuid, String)> =
+        sqlx::query_as("SELECT id, email FROM subscriptions ORDER BY lower(email)")
+            .fetch_all(&db_pool)
+            .await
+            .unwrap();
+    assert_eq!(remaining.len(), 2);
+    assert_eq!(remaining[0].1, "alice@example.com");
+    assert_eq!(remaining[1], (kept, "Bob@example.com".to_string()));
+}
+
+#[sqlx::test]
+async fn migrating_converts_stored_unicode_domains_to_punycode(db_pool: PgPool) {
+    // Stored before signups converted domains, or signed up again since
+    let confirmed = Uuid::new_v4();
+    let resubscribed = Uuid::new_v4();
+    let other = Uuid::new_v4();
+    sqlx::query(
+        r#"INSERT INTO subscriptions (id, email, display_email, name, subscribed_at, status) VALUES
+        ($1, 'bob@bücher.example', 'bob@Bücher.example', 'Bob', now() - interval '1 day', 'CONFIRMED'),
+        ($2, 'bob@xn--bcher-kva.example', 'bob@bücher.example', 'Bob', now(), 'PENDING_CONFIRMATION'),
+        ($3, 'alice@bücher.example', 'alice@bücher.example', 'Alice', now(), 'CONFIRMED')"#,
+    )
+    .bind(confirmed)
+    .bind(resubscribed)
+    .bind(other)
+    .execute(&db_pool)
+    .await
+    .unwrap();
+
+    run_migrations(&db_pool).await.expect("Failed to migrate");
+
+    let remaining: Vec<(Uuid, String)> =
+        sqlx::query_as("SELECT id, email FROM subscriptions ORDER BY email")
+            .fetch_all(&db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        remaining,
+        vec![
+            (other, "alice@xn--bcher-kva.example".to_string()),
+            (confirmed, "bob@xn--bcher-kva.example".to_string()),
+        ]
+    );
+    let deleted: Vec<String> =
+        sqlx::query_scalar("SELECT target_id FROM audit_log WHERE action = 'subscriber_deleted'")
+            .fetch_all(&db_pool)
+            .await
+            .unwrap();
+    assert_eq!(deleted, vec![resubscribed.to_string()]);
+}
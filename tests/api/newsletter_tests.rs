@@ -185,7 +185,7 @@ async fn non_existing_user_is_rejected(db_pool: PgPool) {
     let password = uuid::Uuid::new_v4().to_string();
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletter", app.address))
+        .post(format!("{}/newsletter", app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
             "title": "Newsletter title",
@@ -214,7 +214,7 @@ async fn invalid_password_is_rejected(db_pool: PgPool) {
     let password = uuid::Uuid::new_v4().to_string();
 
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletter", app.address))
+        .post(format!("{}/newsletter", app.address))
         .basic_auth(username, Some(password))
         .json(&serde_json::json!({
             "title": "Newsletter title",
@@ -0,0 +1,294 @@
+use std::time::Duration;
+
+use reqwest::{Method, StatusCode};
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+const USER_EMAIL: &str = "editor@example.com";
+const NEW_PASSWORD: &str = "a-brand-new-password";
+
+async fn set_test_user_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = $2 WHERE user_id = $1",
+        app.test_user.user_id,
+        USER_EMAIL
+    )
+    .execute(&app.db_pool)
+    .await
+    .expect("Failed to set user email");
+}
+
+async fn request_reset(app: &TestApp, email: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/password-reset", app.address))
+        .json(&serde_json::json!({ "email": email }))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn confirm_reset(app: &TestApp, token: &str, password: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/password-reset/confirm", app.address))
+        .json(&serde_json::json!({ "token": token, "password": password }))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn login(app: &TestApp, password: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", app.address))
+        .basic_auth(&app.test_user.username, Some(password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// Links are sent in the background, after the response.
+async fn received_emails(app: &TestApp, expected: usize) -> Vec<wiremock::Request> {
+    for _ in 0..50 {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if requests.len() >= expected {
+            return requests;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    app.email_server.received_requests().await.unwrap()
+}
+
+/// Requests a reset link for the test user and returns it.
+async fn reset_link(app: &TestApp) -> reqwest::Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    set_test_user_email(app).await;
+
+    let response = request_reset(app, USER_EMAIL).await;
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+
+    let emails = received_emails(app, 1).await;
+    assert_eq!(1, emails.len());
+
+    let links = app.get_confirmation_links(&emails[0]);
+    assert_eq!(links.html, links.plain_text);
+    assert_eq!("/password-reset/confirm", links.html.path());
+
+    links.html
+}
+
+/// Requests a reset link for the test user and returns its token.
+async fn reset_token(app: &TestApp) -> String {
+    reset_link(app)
+        .await
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, token)| token.into_owned())
+        .expect("Reset link without a token")
+}
+
+#[sqlx::test]
+async fn reset_link_is_emailed_to_the_user(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let token = reset_token(&app).await;
+
+    let emails = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&emails[0].body).unwrap();
+    assert_eq!(USER_EMAIL, body["To"]);
+    assert!(!token.is_empty());
+}
+
+#[sqlx::test]
+async fn unknown_email_gets_the_same_response_and_no_email(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = request_reset(&app, "nobody@example.com").await;
+
+    assert_eq!(StatusCode::ACCEPTED, response.status());
+    assert_eq!("", response.text().await.unwrap());
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
+
+#[sqlx::test]
+async fn reset_token_sets_a_new_password(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let token = reset_token(&app).await;
+
+    let response = confirm_reset(&app, &token, NEW_PASSWORD).await;
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    assert_eq!(
+        StatusCode::UNAUTHORIZED,
+        login(&app, &app.test_user.password).await.status()
+    );
+    assert_eq!(StatusCode::OK, login(&app, NEW_PASSWORD).await.status());
+
+    let password_hash = sqlx::query_scalar!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(password_hash.starts_with("$argon2id$"));
+}
+
+#[sqlx::test]
+async fn emailed_link_opens_a_form_that_sets_a_new_password(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let link = reset_link(&app).await;
+
+    let response = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert_eq!("no-store", response.headers()["Cache-Control"]);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"action="/password-reset/confirm" method="post""#));
+
+    // Submit the form as a browser would
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .unwrap()
+        .1;
+    assert!(page.contains(&format!(r#"name="token" value="{}""#, token)));
+    let response = reqwest::Client::new()
+        .post(link.as_str().split('?').next().unwrap())
+        .form(&[("token", token.as_ref()), ("password", NEW_PASSWORD)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+
+    assert_eq!(StatusCode::OK, login(&app, NEW_PASSWORD).await.status());
+}
+
+#[sqlx::test]
+async fn reset_form_is_not_shown_for_an_unknown_token(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    for token in ["a".repeat(32), "<script>".into()] {
+        let response = reqwest::Client::new()
+            .get(format!("{}/password-reset/confirm", app.address))
+            .query(&[("token", &token)])
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    }
+}
+
+#[sqlx::test]
+async fn reset_token_can_only_be_used_once(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let token = reset_token(&app).await;
+
+    let response = confirm_reset(&app, &token, NEW_PASSWORD).await;
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    let response = confirm_reset(&app, &token, "yet-another-password").await;
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    assert_eq!(StatusCode::OK, login(&app, NEW_PASSWORD).await.status());
+}
+
+#[sqlx::test]
+async fn expired_reset_token_is_rejected(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let token = reset_token(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = confirm_reset(&app, &token, NEW_PASSWORD).await;
+
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+}
+
+#[sqlx::test]
+async fn short_password_is_rejected_without_using_the_token(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let token = reset_token(&app).await;
+
+    let response = confirm_reset(&app, &token, "short").await;
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+
+    let response = confirm_reset(&app, &token, NEW_PASSWORD).await;
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+}
+
+#[sqlx::test]
+async fn reset_lifts_a_lockout(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let token = reset_token(&app).await;
+    sqlx::query!(
+        r#"INSERT INTO login_failures (key, failures, last_failure_at, locked_until)
+        VALUES ($1, 10, now(), now() + interval '1 hour')"#,
+        format!("username:{}", app.test_user.username)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        StatusCode::TOO_MANY_REQUESTS,
+        login(&app, &app.test_user.password).await.status()
+    );
+
+    confirm_reset(&app, &token, NEW_PASSWORD).await;
+
+    assert_eq!(StatusCode::OK, login(&app, NEW_PASSWORD).await.status());
+}
+
+#[sqlx::test]
+async fn admin_can_set_a_user_email(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let editor = app
+        .create_user(zero2prod_rust::domain::UserRole::Editor)
+        .await;
+
+    let response = app
+        .request_as(
+            &app.test_user,
+            Method::PUT,
+            &format!("/admin/users/{}/email", editor.user_id),
+        )
+        .json(&serde_json::json!({ "email": USER_EMAIL }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    let user: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(USER_EMAIL, user["email"]);
+
+    // Emails are unique across users
+    let response = app
+        .request_as(
+            &app.test_user,
+            Method::PUT,
+            &format!("/admin/users/{}/email", app.test_user.user_id),
+        )
+        .json(&serde_json::json!({ "email": USER_EMAIL.to_uppercase() }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::CONFLICT, response.status());
+}
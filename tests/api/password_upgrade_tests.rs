@@ -0,0 +1,64 @@
+use sqlx::PgPool;
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn list_subscribers_as(app: &TestApp, password: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", &app.address))
+        .basic_auth(&app.test_user.username, Some(password))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query_scalar!(
+        "SELECT password_hash FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch password hash")
+}
+
+#[sqlx::test]
+async fn weak_password_hash_is_upgraded_on_successful_login(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    assert!(stored_password_hash(&app)
+        .await
+        .starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+
+    let response = list_subscribers_as(&app, &app.test_user.password).await;
+    assert_eq!(200, response.status().as_u16());
+
+    assert!(stored_password_hash(&app)
+        .await
+        .starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+
+    // The upgraded hash still verifies the same password
+    let response = list_subscribers_as(&app, &app.test_user.password).await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[sqlx::test]
+async fn password_hash_is_kept_on_failed_login(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let password_hash = stored_password_hash(&app).await;
+
+    let response = list_subscribers_as(&app, "wrong-password").await;
+    assert_eq!(401, response.status().as_u16());
+
+    assert_eq!(password_hash, stored_password_hash(&app).await);
+}
+
+#[sqlx::test]
+async fn up_to_date_password_hash_is_not_rewritten(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    list_subscribers_as(&app, &app.test_user.password).await;
+    let password_hash = stored_password_hash(&app).await;
+
+    let response = list_subscribers_as(&app, &app.test_user.password).await;
+    assert_eq!(200, response.status().as_u16());
+
+    assert_eq!(password_hash, stored_password_hash(&app).await);
+}
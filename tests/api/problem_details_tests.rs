@@ -0,0 +1,90 @@
+use sqlx::PgPool;
+
+use crate::helpers::spawn_app;
+
+#[sqlx::test]
+async fn errors_are_returned_as_problem_details(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = app
+        .post_subscriptions_json(serde_json::json!({ "name": "", "email": "bruce@wayne.com" }))
+        .await;
+
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+    assert_eq!(
+        "application/problem+json",
+        response.headers()["Content-Type"]
+    );
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "about:blank");
+    assert_eq!(body["title"], "Bad Request");
+    assert_eq!(body["status"], 400);
+    assert!(body["detail"].as_str().is_some());
+    assert!(body["request_id"].as_str().is_some_and(|id| !id.is_empty()));
+}
+
+#[sqlx::test]
+async fn malformed_requests_are_returned_as_problem_details(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let test_cases = vec![
+        (
+            reqwest::Client::new()
+                .post(format!("{}/subscriptions", app.address))
+                .header("Content-Type", "application/json")
+                .body("{not json"),
+            reqwest::StatusCode::BAD_REQUEST,
+            "Malformed json body",
+        ),
+        (
+            reqwest::Client::new().get(format!("{}/subscriptions/confirm", app.address)),
+            reqwest::StatusCode::BAD_REQUEST,
+            "Missing query parameter",
+        ),
+        (
+            reqwest::Client::new().get(format!("{}/no-such-route", app.address)),
+            reqwest::StatusCode::NOT_FOUND,
+            "Unknown route",
+        ),
+    ];
+
+    for (request, status, description) in test_cases {
+        let response = request.send().await.expect("Failed to execute request.");
+
+        assert_eq!(
+            status,
+            response.status(),
+            "Unexpected status for {}",
+            description
+        );
+        assert_eq!(
+            "application/problem+json",
+            response.headers()["Content-Type"],
+            "Unexpected content type for {}",
+            description
+        );
+
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["status"], status.as_u16());
+        assert!(body["request_id"].as_str().is_some());
+    }
+}
+
+#[sqlx::test]
+async fn unknown_confirmation_token_is_returned_as_problem_details(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(reqwest::StatusCode::NOT_FOUND, response.status());
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], 404);
+    assert_eq!(body["title"], "Not Found");
+}
@@ -0,0 +1,211 @@
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod_rust::configuration::{RateLimitStoreKind, Settings};
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+fn limit_per_ip(config: &mut Settings) {
+    config.rate_limit.per_ip.capacity = 2;
+}
+
+async fn assert_rate_limited(response: reqwest::Response) {
+    assert_eq!(reqwest::StatusCode::TOO_MANY_REQUESTS, response.status());
+    assert_eq!(
+        "application/problem+json",
+        response.headers()["Content-Type"]
+    );
+
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0);
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], 429);
+}
+
+async fn post_invalid_subscriptions(app: &TestApp, count: usize) {
+    for _ in 0..count {
+        let response = app.post_subscriptions("name=Bruce%20Wayne".into()).await;
+        assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+    }
+}
+
+#[sqlx::test]
+async fn subscribe_returns_429_once_the_client_ip_runs_out_of_tokens(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, limit_per_ip).await;
+
+    post_invalid_subscriptions(&app, 2).await;
+
+    let response = app.post_subscriptions("name=Bruce%20Wayne".into()).await;
+
+    assert_rate_limited(response).await;
+}
+
+#[sqlx::test]
+async fn confirm_returns_429_once_the_client_ip_runs_out_of_tokens(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, limit_per_ip).await;
+    let confirm = || reqwest::get(format!("{}/subscriptions/confirm", app.address));
+
+    for _ in 0..2 {
+        let response = confirm().await.unwrap();
+        assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+    }
+
+    assert_rate_limited(confirm().await.unwrap()).await;
+}
+
+#[sqlx::test]
+async fn subscribe_returns_429_once_the_email_runs_out_of_tokens(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        config.rate_limit.per_email.capacity = 1;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=Bruce%20Wayne&email=bruce%40wayne.com".into())
+        .await;
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+
+    // Same address, differently cased
+    let response = app
+        .post_subscriptions("name=Bruce%20Wayne&email=Bruce%40Wayne.com".into())
+        .await;
+    assert_rate_limited(response).await;
+
+    // Other addresses are not affected
+    let response = app.post_subscriptions("name=Bruce%20Wayne".into()).await;
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+}
+
+#[sqlx::test]
+async fn rate_limits_can_be_stored_in_postgres(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        limit_per_ip(config);
+        config.rate_limit.store = RateLimitStoreKind::Postgres;
+    })
+    .await;
+
+    post_invalid_subscriptions(&app, 2).await;
+
+    let response = app.post_subscriptions("name=Bruce%20Wayne".into()).await;
+    assert_rate_limited(response).await;
+
+    let bucket = sqlx::query!("SELECT key, tokens FROM rate_limit_buckets")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch rate limit bucket");
+
+    assert_eq!(bucket.key, "ip:127.0.0.1");
+    assert!(bucket.tokens < 1.0);
+}
+
+#[sqlx::test]
+async fn requests_are_not_limited_when_rate_limiting_is_disabled(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        limit_per_ip(config);
+        config.rate_limit.enabled = false;
+    })
+    .await;
+
+    post_invalid_subscriptions(&app, 5).await;
+}
+
+#[sqlx::test]
+async fn stale_postgres_buckets_are_deleted(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        config.rate_limit.store = RateLimitStoreKind::Postgres;
+    })
+    .await;
+
+    // Both buckets of the default settings refill within an hour
+    sqlx::query!(
+        r#"INSERT INTO rate_limit_buckets (key, tokens, updated_at) VALUES
+        ('ip:10.0.0.1', 0, now() - interval '1 day'),
+        ('ip:10.0.0.2', 0, now())"#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    post_invalid_subscriptions(&app, 1).await;
+
+    let keys = sqlx::query_scalar!("SELECT key FROM rate_limit_buckets ORDER BY key")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(keys, vec!["ip:10.0.0.2", "ip:127.0.0.1"]);
+}
+
+#[sqlx::test]
+async fn subscriptions_and_password_resets_have_separate_email_buckets(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        config.rate_limit.per_email.capacity = 1;
+    })
+    .await;
+    let request_reset = || {
+        reqwest::Client::new()
+            .post(format!("{}/password-reset", app.address))
+            .json(&serde_json::json!({ "email": "bruce@wayne.com" }))
+            .send()
+    };
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=Bruce%20Wayne&email=bruce%40wayne.com".into())
+        .await;
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+
+    let response = request_reset().await.unwrap();
+    assert_eq!(reqwest::StatusCode::ACCEPTED, response.status());
+
+    assert_rate_limited(request_reset().await.unwrap()).await;
+}
+
+#[sqlx::test]
+async fn client_ip_is_the_address_appended_by_the_proxy(db_pool: PgPool) {
+    let app = spawn_app_with(db_pool, |config| {
+        limit_per_ip(config);
+        config.rate_limit.store = RateLimitStoreKind::Postgres;
+        config.application.trust_forwarded_for = true;
+    })
+    .await;
+    let post_from = |spoofed: &str| {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", format!("{}, 198.51.100.7", spoofed))
+            .body("name=Bruce%20Wayne")
+            .send()
+    };
+
+    // The client rotates the entries it controls
+    for spoofed in ["203.0.113.1", "203.0.113.2"] {
+        let response = post_from(spoofed).await.unwrap();
+        assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+    }
+
+    assert_rate_limited(post_from("203.0.113.3").await.unwrap()).await;
+    let keys = sqlx::query_scalar!("SELECT key FROM rate_limit_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(keys, vec!["ip:198.51.100.7"]);
+}
@@ -0,0 +1,100 @@
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::spawn_app;
+
+fn request_id(response: &reqwest::Response) -> String {
+    response.headers()["X-Request-Id"]
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[sqlx::test]
+async fn responses_carry_a_generated_request_id(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let first = reqwest::get(format!("{}/ping", app.address)).await.unwrap();
+    let second = reqwest::get(format!("{}/ping", app.address)).await.unwrap();
+
+    assert!(!request_id(&first).is_empty());
+    assert_ne!(request_id(&first), request_id(&second));
+}
+
+#[sqlx::test]
+async fn a_valid_caller_request_id_is_reused(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/ping", app.address))
+        .header("X-Request-Id", "support-ticket-42")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(request_id(&response), "support-ticket-42");
+}
+
+#[sqlx::test]
+async fn an_invalid_caller_request_id_is_replaced(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    for invalid_id in ["<script>alert(1)</script>", &"a".repeat(129)] {
+        let response = reqwest::Client::new()
+            .get(format!("{}/ping", app.address))
+            .header("X-Request-Id", invalid_id)
+            .send()
+            .await
+            .unwrap();
+
+        let id = request_id(&response);
+        assert!(!id.is_empty());
+        assert_ne!(id, invalid_id);
+    }
+}
+
+#[sqlx::test]
+async fn error_responses_include_the_request_id(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("X-Request-Id", "support-ticket-42")
+        .json(&serde_json::json!({ "name": "", "email": "bruce@wayne.com" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+    assert_eq!(request_id(&response), "support-ticket-42");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["request_id"], "support-ticket-42");
+}
+
+#[sqlx::test]
+async fn the_request_id_is_sent_to_the_email_provider(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("X-Request-Id", "support-ticket-42")
+        .json(&serde_json::json!({ "name": "Bruce Wayne", "email": "bruce@wayne.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Metadata"]["request_id"], "support-ticket-42");
+}
@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const EMAIL_DELAY: Duration = Duration::from_millis(1000);
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=bruce%20wayne&email=bruce%40wayne.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn mount_slow_email_provider(app: &TestApp, delay: Duration) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(delay))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[sqlx::test]
+async fn in_flight_publish_completes_after_shutdown_is_requested(db_pool: PgPool) {
+    let mut app = spawn_app(db_pool).await;
+    create_confirmed_subscriber(&app).await;
+    mount_slow_email_provider(&app, EMAIL_DELAY).await;
+
+    let address = app.address.clone();
+    let username = app.test_user.username.clone();
+    let password = app.test_user.password.clone();
+    let publish = tokio::spawn(async move {
+        reqwest::Client::new()
+            .post(format!("{}/newsletter", address))
+            .basic_auth(username, Some(password))
+            .json(&serde_json::json!({
+                "title": "Newsletter Title",
+                "content": {
+                    "text": "Newsletter as plain text",
+                    "html": "<p>Newsletter as html</p>",
+                }
+            }))
+            .send()
+            .await
+    });
+    // Let the send reach the email provider before shutting down
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    app.shutdown().await;
+
+    let response = publish.await.unwrap().expect("Request was dropped");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 2);
+}
+
+#[sqlx::test]
+async fn connections_are_refused_and_the_pool_closed_after_shutdown(db_pool: PgPool) {
+    let mut app = spawn_app(db_pool).await;
+
+    app.shutdown().await;
+
+    let outcome = reqwest::get(format!("{}/ping", app.address)).await;
+    assert!(outcome.is_err());
+    assert!(app.db_pool.is_closed());
+}
+
+#[sqlx::test]
+async fn background_emails_are_sent_before_shutting_down(db_pool: PgPool) {
+    let mut app = spawn_app(db_pool).await;
+    sqlx::query!(
+        "UPDATE users SET email = 'editor@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    mount_slow_email_provider(&app, EMAIL_DELAY).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/password-reset", app.address))
+        .json(&serde_json::json!({ "email": "editor@example.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let started_at = Instant::now();
+    app.shutdown().await;
+
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+    assert!(started_at.elapsed() >= EMAIL_DELAY / 2);
+}
+
+#[sqlx::test]
+async fn shutdown_does_not_wait_past_the_grace_period(db_pool: PgPool) {
+    let mut app = spawn_app_with(db_pool, |c| {
+        c.application.shutdown_grace_period_seconds = 1;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    mount_slow_email_provider(&app, Duration::from_secs(8)).await;
+
+    let address = app.address.clone();
+    let username = app.test_user.username.clone();
+    let password = app.test_user.password.clone();
+    tokio::spawn(async move {
+        reqwest::Client::new()
+            .post(format!("{}/newsletter", address))
+            .basic_auth(username, Some(password))
+            .json(&serde_json::json!({
+                "title": "Newsletter Title",
+                "content": { "text": "text", "html": "<p>html</p>" }
+            }))
+            .send()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let started_at = Instant::now();
+    app.shutdown().await;
+
+    assert!(started_at.elapsed() < Duration::from_secs(4));
+}
@@ -60,12 +60,84 @@ async fn confirmation_link_confirms_a_subscriber(db_pool: PgPool) {
 
     let _ = reqwest::get(confirmation_links.html).await.unwrap();
 
-    let subscriber = sqlx::query!("SELECT email, name, status FROM subscriptions")
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("Failed to fetch subscriptions");
+    let subscriber =
+        sqlx::query!(r#"SELECT email, name, status::text AS "status!" FROM subscriptions"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .expect("Failed to fetch subscriptions");
 
     assert_eq!(subscriber.email, "bruce@wayne.com");
     assert_eq!(subscriber.name, "Bruce Wayne");
     assert_eq!(subscriber.status, "CONFIRMED");
 }
+
+#[sqlx::test]
+async fn confirmation_link_does_not_confirm_an_unsubscribed_subscriber(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let body = "name=Bruce%20Wayne&email=bruce%40wayne.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    sqlx::query!("UPDATE subscriptions SET status = 'UNSUBSCRIBED'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+}
+
+#[sqlx::test]
+async fn confirmation_is_recorded_in_status_history(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let body = "name=Bruce%20Wayne&email=bruce%40wayne.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let history = sqlx::query!(
+        r#"
+        SELECT from_status::text AS "from_status", to_status::text AS "to_status!"
+        FROM subscription_status_history
+        ORDER BY changed_at
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].from_status, None);
+    assert_eq!(history[0].to_status, "PENDING_CONFIRMATION");
+    assert_eq!(
+        history[1].from_status.as_deref(),
+        Some("PENDING_CONFIRMATION")
+    );
+    assert_eq!(history[1].to_status, "CONFIRMED");
+}
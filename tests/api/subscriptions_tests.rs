@@ -39,7 +39,7 @@ async fn subscribe_saves_data_in_db(db_pool: PgPool) {
 
     app.post_subscriptions(body.into()).await;
 
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+    let saved = sqlx::query!(r#"SELECT email, name, status::text AS "status!" FROM subscriptions"#)
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch subscriptions");
@@ -149,4 +149,299 @@ async fn subscribe_fails_if_fatal_database_error(db_pool: PgPool) {
         response.status(),
         reqwest::StatusCode::INTERNAL_SERVER_ERROR
     );
+
+    // The cause chain must not leak to the client
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], 500);
+    assert!(body.get("detail").is_none());
+}
+
+#[sqlx::test]
+async fn subscribe_normalises_email_and_keeps_entered_form(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let body = "name=Bruce%20Wayne&email=%20Bruce%40Wayne.COM%20";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+
+    let saved = sqlx::query!("SELECT email, display_email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch subscriptions");
+
+    assert_eq!(saved.email, "Bruce@wayne.com");
+    assert_eq!(saved.display_email, "Bruce@Wayne.COM");
+}
+
+#[sqlx::test]
+async fn emails_differing_only_by_case_are_the_same_subscriber(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=Bob&email=Bob%40Example.com".into())
+        .await;
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+
+    let response = app
+        .post_subscriptions("name=Bob&email=bob%40example.com".into())
+        .await;
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+
+    let saved = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch subscriptions");
+
+    assert_eq!(saved.count, 1);
+}
+
+#[sqlx::test]
+async fn subscribing_again_while_pending_resends_a_working_link(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let body = "name=Bruce%20Wayne&email=bruce%40wayne.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first = app.get_confirmation_links(&email_requests[0]);
+    let second = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first.html, second.html);
+
+    let response = reqwest::get(second.html).await.unwrap();
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+
+    let status = sqlx::query_scalar!(r#"SELECT status::text AS "status!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "CONFIRMED");
+}
+
+#[sqlx::test]
+async fn subscribing_again_when_confirmed_answers_alike_without_an_email(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let body = "name=Bruce%20Wayne&email=bruce%40wayne.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_confirmation_links(email_request).html;
+    reqwest::get(link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let second = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(first.status(), second.status());
+    assert_eq!(first.text().await.unwrap(), second.text().await.unwrap());
+}
+
+#[sqlx::test]
+async fn subscribe_rejects_undeliverable_emails_with_a_reason(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let test_cases = vec![
+        (
+            "name=Bruce%20Wayne&email=bruce%40mailinator.com",
+            "mailinator.com is a disposable email domain",
+        ),
+        (
+            "name=Bruce%20Wayne&email=postmaster%40wayne.com",
+            "postmaster@wayne.com is a role address, please subscribe with a personal address",
+        ),
+    ];
+
+    for (body, reason) in test_cases {
+        let response = app.post_subscriptions(body.into()).await;
+
+        assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(reason, body["detail"]);
+    }
+}
+
+#[sqlx::test]
+async fn subscribe_accepts_json_body(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "Bruce Wayne",
+            "email": "bruce@wayne.com",
+        }))
+        .await;
+
+    assert_eq!(reqwest::StatusCode::OK, response.status());
+
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch subscriptions");
+
+    assert_eq!(saved.email, "bruce@wayne.com");
+    assert_eq!(saved.name, "Bruce Wayne");
+}
+
+#[sqlx::test]
+async fn subscribe_returns_400_for_json_body_with_missing_fields(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({ "name": "Bruce Wayne" }),
+            "Missing email",
+        ),
+        (
+            serde_json::json!({ "email": "bruce@wayne.com" }),
+            "Missing name",
+        ),
+        (serde_json::json!({}), "Missing name and email"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_subscriptions_json(body).await;
+
+        assert_eq!(
+            reqwest::StatusCode::BAD_REQUEST,
+            response.status(),
+            "Api did not fail with 400 BAD_REQUEST for {}",
+            description
+        );
+    }
+}
+
+#[sqlx::test]
+async fn subscribe_returns_field_errors_as_json_for_json_clients(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "",
+            "email": "not-an-email",
+        }))
+        .await;
+
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    let fields: Vec<&str> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| {
+            assert!(e["message"].as_str().is_some_and(|m| !m.is_empty()));
+            e["field"].as_str().unwrap()
+        })
+        .collect();
+
+    assert_eq!(vec!["name", "email"], fields);
+}
+
+#[sqlx::test]
+async fn subscribe_returns_field_errors_for_form_submissions(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let response = app
+        .post_subscriptions("name=Bruce%20Wayne&email=bruce%40mailinator.com".into())
+        .await;
+
+    assert_eq!(reqwest::StatusCode::BAD_REQUEST, response.status());
+    assert_eq!(
+        "application/problem+json",
+        response.headers()["Content-Type"]
+    );
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert_eq!(
+        body["errors"][0]["message"],
+        "mailinator.com is a disposable email domain"
+    );
+}
+
+#[sqlx::test]
+async fn json_and_form_bodies_get_the_same_error_responses(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    let test_cases = vec![
+        (
+            "name=&email=not-an-email",
+            serde_json::json!({ "name": "", "email": "not-an-email" }),
+            "invalid fields",
+        ),
+        (
+            "name=Bruce%20Wayne",
+            serde_json::json!({ "name": "Bruce Wayne" }),
+            "missing email",
+        ),
+    ];
+
+    for (form, json, description) in test_cases {
+        let mut bodies = vec![];
+        for response in [
+            app.post_subscriptions(form.into()).await,
+            app.post_subscriptions_json(json).await,
+        ] {
+            assert_eq!(
+                reqwest::StatusCode::BAD_REQUEST,
+                response.status(),
+                "{}",
+                description
+            );
+            assert_eq!(
+                "application/problem+json",
+                response.headers()["Content-Type"],
+                "{}",
+                description
+            );
+            bodies.push(response.json::<serde_json::Value>().await.unwrap());
+        }
+
+        assert_eq!(bodies[0]["status"], 400, "{}", description);
+        assert_eq!(bodies[0]["errors"], bodies[1]["errors"], "{}", description);
+    }
 }
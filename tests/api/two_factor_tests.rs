@@ -0,0 +1,216 @@
+use reqwest::{Method, StatusCode};
+use sqlx::PgPool;
+use totp_rs::TOTP;
+
+use crate::helpers::{spawn_app, TestApp};
+
+struct TwoFactor {
+    totp: TOTP,
+    recovery_codes: Vec<String>,
+}
+
+impl TwoFactor {
+    /// A code of the next step, as the current one was used to confirm.
+    fn next_code(&self) -> String {
+        let now = chrono::Utc::now().timestamp() as u64;
+        self.totp.generate(now + 30)
+    }
+}
+
+fn two_factor_request(app: &TestApp, method: Method, path: &str) -> reqwest::RequestBuilder {
+    app.request_as(&app.test_user, method, &format!("/two-factor{}", path))
+}
+
+fn list_subscribers(app: &TestApp) -> reqwest::RequestBuilder {
+    app.request_as(&app.test_user, Method::GET, "/admin/subscribers")
+}
+
+async fn start_enrolment(app: &TestApp) -> TOTP {
+    let response = two_factor_request(app, Method::POST, "")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::CREATED, response.status());
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    let uri = body["provisioning_uri"].as_str().unwrap();
+    assert!(uri.starts_with(&format!(
+        "otpauth://totp/zero2prod:{}?",
+        app.test_user.username
+    )));
+
+    TOTP::from_url(uri).expect("Invalid provisioning URI")
+}
+
+async fn enable_two_factor(app: &TestApp) -> TwoFactor {
+    let totp = start_enrolment(app).await;
+
+    let response = two_factor_request(app, Method::POST, "/confirm")
+        .json(&serde_json::json!({ "code": totp.generate_current().unwrap() }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    let recovery_codes: Vec<String> =
+        serde_json::from_value(body["recovery_codes"].clone()).unwrap();
+    assert_eq!(10, recovery_codes.len());
+
+    TwoFactor {
+        totp,
+        recovery_codes,
+    }
+}
+
+#[sqlx::test]
+async fn second_factor_is_not_required_until_enrolment_is_confirmed(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+
+    start_enrolment(&app).await;
+
+    let response = list_subscribers(&app).send().await.unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+}
+
+#[sqlx::test]
+async fn enrolment_is_not_confirmed_with_an_invalid_code(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    start_enrolment(&app).await;
+
+    let response = two_factor_request(&app, Method::POST, "/confirm")
+        .json(&serde_json::json!({ "code": "000000x" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+
+    let response = list_subscribers(&app).send().await.unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+}
+
+#[sqlx::test]
+async fn password_alone_is_rejected_once_two_factor_is_enabled(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    enable_two_factor(&app).await;
+
+    let response = list_subscribers(&app).send().await.unwrap();
+
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    assert!(response.headers().contains_key("WWW-Authenticate"));
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert!(problem["detail"].as_str().unwrap().contains("X-TOTP-Code"));
+}
+
+#[sqlx::test]
+async fn totp_code_is_accepted_once(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let two_factor = enable_two_factor(&app).await;
+    let code = two_factor.next_code();
+
+    let response = list_subscribers(&app)
+        .header("X-TOTP-Code", &code)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+
+    let response = list_subscribers(&app)
+        .header("X-TOTP-Code", &code)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[sqlx::test]
+async fn invalid_totp_code_counts_as_a_failed_login(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    enable_two_factor(&app).await;
+
+    let response = list_subscribers(&app)
+        .header("X-TOTP-Code", "not-a-code")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+    let failures = sqlx::query_scalar!(
+        "SELECT failures FROM login_failures WHERE key = $1",
+        format!("username:{}", app.test_user.username)
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch login failures");
+    assert_eq!(1, failures);
+}
+
+#[sqlx::test]
+async fn recovery_code_is_accepted_once(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let two_factor = enable_two_factor(&app).await;
+    // Codes are accepted regardless of case
+    let code = two_factor.recovery_codes[0].to_uppercase();
+
+    let response = list_subscribers(&app)
+        .header("X-TOTP-Code", &code)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+
+    let response = list_subscribers(&app)
+        .header("X-TOTP-Code", &code)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+}
+
+#[sqlx::test]
+async fn two_factor_can_be_disabled_with_a_valid_code(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    let two_factor = enable_two_factor(&app).await;
+
+    let response = two_factor_request(&app, Method::DELETE, "")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+    let response = two_factor_request(&app, Method::DELETE, "")
+        .header("X-TOTP-Code", two_factor.next_code())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+
+    let response = list_subscribers(&app).send().await.unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+}
+
+#[sqlx::test]
+async fn admin_can_reset_two_factor_of_another_user(db_pool: PgPool) {
+    let app = spawn_app(db_pool).await;
+    enable_two_factor(&app).await;
+    let admin = app
+        .create_user(zero2prod_rust::domain::UserRole::Admin)
+        .await;
+
+    let response = app
+        .request_as(
+            &admin,
+            Method::DELETE,
+            &format!("/admin/users/{}/two-factor", app.test_user.user_id),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(StatusCode::OK, response.status());
+    let user: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(false, user["two_factor_enabled"]);
+
+    let response = list_subscribers(&app).send().await.unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+}